@@ -0,0 +1,258 @@
+// This file holds knowledge of specific endgames that the general eval gets
+// wrong. The material on the board picks out the endgame: some have their
+// own evaluators that replace the general eval entirely, such as driving a
+// bare king into a corner to mate it, and some only scale the general eval
+// down towards a draw, such as bishops of opposite colours.
+
+use crate::eval::*;
+use crate::kpk;
+use crate::pawns::distance;
+use cozy_chess::*;
+
+// Added to the score of endgames that are won with correct play, so that
+// the search prefers reaching them over keeping more material.
+const KNOWN_WIN: EvalInt = 2000;
+
+// Scale factors are out of SCALE_NORMAL, which leaves the eval as it is.
+pub const SCALE_NORMAL: EvalInt = 64;
+const SCALE_OPPOSITE_BISHOPS: EvalInt = 24;
+const SCALE_MINOR_UP: EvalInt = 16;
+
+// How many of each piece color has.
+fn count(board: &Board, color: Color, piece: Piece) -> u32 {
+    board.colored_pieces(color, piece).len()
+}
+
+// Whether color has nothing but its king.
+fn is_bare(board: &Board, color: Color) -> bool {
+    board.colors(color).len() == 1
+}
+
+// Whether color's pieces are exactly these, besides the king.
+fn has_exactly(board: &Board, color: Color, pieces: &[(Piece, u32)]) -> bool {
+    let total: u32 = pieces.iter().map(|&(_, n)| n).sum();
+    board.colors(color).len() == total + 1 && pieces.iter().all(|&(piece, n)| count(board, color, piece) == n)
+}
+
+// Endgame value of color's pieces other than pawns.
+fn non_pawn_material(board: &Board, color: Color) -> EvalInt {
+    [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen]
+        .iter()
+        .map(|&piece| EG_VALUES[piece as usize] * count(board, color, piece) as EvalInt)
+        .sum()
+}
+
+// Endgame value of all of color's pieces.
+fn material(board: &Board, color: Color) -> EvalInt {
+    non_pawn_material(board, color) + EG_VALUES[Piece::Pawn as usize] * count(board, color, Piece::Pawn) as EvalInt
+}
+
+// Grows as a square nears the edge, and most of all in a corner.
+fn push_to_edge(square: Square) -> EvalInt {
+    let file = square.file() as EvalInt;
+    let rank = square.rank() as EvalInt;
+    let from_edge = file.min(7 - file) + rank.min(7 - rank);
+    60 - 10 * from_edge
+}
+
+// Grows as two squares come closer together.
+fn push_close(a: Square, b: Square) -> EvalInt {
+    70 - 10 * distance(a, b)
+}
+
+// King and enough material to mate against a bare king. The weak king is
+// driven to the edge with the strong king close by, which is how every
+// such mate is delivered.
+fn kxk(board: &Board, strong: Color) -> EvalInt {
+    let strong_king = board.king(strong);
+    let weak_king = board.king(!strong);
+    KNOWN_WIN + material(board, strong) + push_to_edge(weak_king) + push_close(strong_king, weak_king)
+}
+
+// King, bishop and knight against a bare king. Mate is only possible in a
+// corner the bishop covers, so the weak king is driven towards one of those.
+fn kbnk(board: &Board, strong: Color) -> EvalInt {
+    let strong_king = board.king(strong);
+    let weak_king = board.king(!strong);
+    let corners = if (board.colored_pieces(strong, Piece::Bishop) & BitBoard::DARK_SQUARES).is_empty() {
+        [Square::A8, Square::H1]
+    } else {
+        [Square::A1, Square::H8]
+    };
+    let to_corner = corners.iter().map(|&corner| distance(weak_king, corner)).min().unwrap_or(0);
+    KNOWN_WIN + material(board, strong) + 70 - 10 * to_corner + push_close(strong_king, weak_king)
+}
+
+// King and pawn against king, looked up in the bitbase. A won position is
+// worth a little more the further the pawn has come, so that the search
+// pushes it on.
+fn kpk(board: &Board, strong: Color) -> EvalInt {
+    let Some(pawn) = board.colored_pieces(strong, Piece::Pawn).next_square() else {
+        return 0;
+    };
+    if !kpk::probe(strong, board.king(strong), board.king(!strong), pawn, board.side_to_move() == strong) {
+        return 0;
+    }
+    KNOWN_WIN + EG_VALUES[Piece::Pawn as usize] + 20 * pawn.rank().relative_to(strong) as EvalInt
+}
+
+// King and rook against king and pawn. Usually a win, unless the pawn is far
+// advanced with its king beside it and the strong king is far away.
+fn krkp(board: &Board, strong: Color) -> EvalInt {
+    let strong_king = board.king(strong);
+    let weak_king = board.king(!strong);
+    let rook = board.colored_pieces(strong, Piece::Rook).next_square().unwrap_or(strong_king);
+    let Some(pawn) = board.colored_pieces(!strong, Piece::Pawn).next_square() else {
+        return 0;
+    };
+    let promotion = Square::new(pawn.file(), Rank::First.relative_to(strong));
+    let rank = |square: Square| square.rank().relative_to(strong) as i32;
+    let tempo = (board.side_to_move() == strong) as i32;
+    let rook_value = EG_VALUES[Piece::Rook as usize];
+
+    if strong_king.file() == pawn.file() && rank(strong_king) < rank(pawn) {
+        // The strong king stands in front of the pawn.
+        rook_value - distance(strong_king, pawn)
+    } else if distance(weak_king, pawn) >= 4 - tempo && distance(weak_king, rook) >= 3 {
+        // The pawn is too far from its king to be defended.
+        rook_value - distance(strong_king, pawn)
+    } else if rank(weak_king) <= 2 && distance(weak_king, pawn) == 1 && rank(strong_king) >= 3 && distance(strong_king, pawn) > 2 + tempo {
+        // The pawn is escorted far up the board with the strong king too
+        // far away to help: a likely draw.
+        80 - 8 * distance(strong_king, pawn)
+    } else {
+        let step = if strong == Color::White { -1 } else { 1 };
+        let next = pawn.offset(0, step);
+        200 - 8 * (distance(strong_king, next) - distance(weak_king, next) - distance(pawn, promotion))
+    }
+}
+
+// Picks the specialised evaluator for the material on the board, if there is
+// one, and scores the position with it.
+// Returns: The score from the side to move's point of view, or None if the
+// general eval should be used
+pub fn evaluate_endgame(board: &Board) -> Option<EvalInt> {
+    for strong in Color::ALL {
+        let weak = !strong;
+        let score = if is_bare(board, weak) {
+            if has_exactly(board, strong, &[(Piece::Bishop, 1), (Piece::Knight, 1)]) {
+                kbnk(board, strong)
+            } else if has_exactly(board, strong, &[(Piece::Pawn, 1)]) {
+                kpk(board, strong)
+            } else if count(board, strong, Piece::Queen) + count(board, strong, Piece::Rook) > 0
+                || (!(board.colored_pieces(strong, Piece::Bishop) & BitBoard::DARK_SQUARES).is_empty()
+                    && !(board.colored_pieces(strong, Piece::Bishop) & BitBoard::LIGHT_SQUARES).is_empty())
+            {
+                kxk(board, strong)
+            } else {
+                continue;
+            }
+        } else if has_exactly(board, strong, &[(Piece::Rook, 1)]) && has_exactly(board, weak, &[(Piece::Pawn, 1)]) {
+            krkp(board, strong)
+        } else {
+            continue;
+        };
+        return Some(if board.side_to_move() == strong { score } else { -score });
+    }
+    None
+}
+
+// Works out how far to scale down strong's advantage in material
+// configurations that are hard or impossible to win: no pawns and at most a
+// minor piece more or only bishops of one colour, bishops of opposite
+// colours, and a bishop with rook pawns
+// whose queening square it does not cover while the enemy king holds it.
+// Returns: The scale factor, out of SCALE_NORMAL
+pub fn scale_factor(board: &Board, strong: Color) -> EvalInt {
+    let weak = !strong;
+    let strong_pawns = board.colored_pieces(strong, Piece::Pawn);
+    let bishops = board.pieces(Piece::Bishop);
+    let heavy_or_knights = board.pieces(Piece::Knight) | board.pieces(Piece::Rook) | board.pieces(Piece::Queen);
+
+    if strong_pawns.is_empty() {
+        let strong_material = non_pawn_material(board, strong);
+        let bishop = EG_VALUES[Piece::Bishop as usize];
+        if strong_material <= bishop || has_exactly(board, strong, &[(Piece::Knight, 2)]) {
+            return 0;
+        }
+        // Bishops that all run on one colour can never give check to a king
+        // on the other colour, so they cannot mate.
+        let strong_bishops = board.colored_pieces(strong, Piece::Bishop);
+        if board.colors(strong) == strong_bishops | board.king(strong).bitboard()
+            && ((strong_bishops & BitBoard::DARK_SQUARES).is_empty() || (strong_bishops & BitBoard::LIGHT_SQUARES).is_empty())
+        {
+            return 0;
+        }
+        if strong_material - non_pawn_material(board, weak) <= bishop {
+            return SCALE_MINOR_UP;
+        }
+    }
+
+    if heavy_or_knights.is_empty()
+        && count(board, strong, Piece::Bishop) == 1
+        && count(board, weak, Piece::Bishop) == 1
+        && (bishops & BitBoard::DARK_SQUARES).len() == 1
+    {
+        return SCALE_OPPOSITE_BISHOPS;
+    }
+
+    if (board.colored_pieces(strong, Piece::Knight) | board.colored_pieces(strong, Piece::Rook) | board.colored_pieces(strong, Piece::Queen)).is_empty()
+        && count(board, strong, Piece::Bishop) > 0
+        && let Some(pawn) = strong_pawns.next_square()
+        && ((strong_pawns & !File::A.bitboard()).is_empty() || (strong_pawns & !File::H.bitboard()).is_empty())
+    {
+        let promotion = Square::new(pawn.file(), Rank::Eighth.relative_to(strong));
+        let promotion_dark = BitBoard::DARK_SQUARES.has(promotion);
+        let bishops_dark = board.colored_pieces(strong, Piece::Bishop) & BitBoard::DARK_SQUARES;
+        let covers = if promotion_dark { !bishops_dark.is_empty() } else { bishops_dark.len() < count(board, strong, Piece::Bishop) };
+        if !covers && distance(board.king(weak), promotion) <= 1 {
+            return 0;
+        }
+    }
+
+    SCALE_NORMAL
+}
+
+#[cfg(test)]
+mod tests {
+    use cozy_chess::*;
+    use crate::endgame::*;
+
+    fn board(fen: &str) -> Board {
+        Board::from_fen(fen, false).unwrap()
+    }
+
+    #[test]
+    fn test_dispatch() {
+        // KQK from both sides' point of view.
+        let kqk = board("8/8/8/3k4/8/8/8/Q3K3 w - - 0 1");
+        assert!(evaluate_endgame(&kqk).unwrap() > KNOWN_WIN);
+        let kqk = board("8/8/8/3k4/8/8/8/Q3K3 b - - 0 1");
+        assert!(evaluate_endgame(&kqk).unwrap() < -KNOWN_WIN);
+        // The bare king is better off in the centre than in the corner.
+        let corner = board("k7/8/8/8/8/8/8/1R2K3 w - - 0 1");
+        let centre = board("8/8/8/3k4/8/8/8/1R2K3 w - - 0 1");
+        assert!(evaluate_endgame(&corner) > evaluate_endgame(&centre));
+        // Material without a specialised evaluator.
+        assert_eq!(evaluate_endgame(&Board::default()), None);
+        assert_eq!(evaluate_endgame(&board("8/8/8/3k4/8/8/8/N3K3 w - - 0 1")), None);
+    }
+
+    #[test]
+    fn test_scale_factor() {
+        assert_eq!(scale_factor(&board("8/8/8/3k4/8/8/8/N3K3 w - - 0 1"), Color::White), 0);
+        assert_eq!(scale_factor(&board("8/8/8/3k4/8/8/8/B3K3 w - - 0 1"), Color::White), 0);
+        assert_eq!(scale_factor(&board("8/8/8/3k4/8/8/8/NN2K3 w - - 0 1"), Color::White), 0);
+        // Two bishops on the same colour cannot mate; on both colours they can.
+        assert_eq!(scale_factor(&board("8/8/8/3k4/8/8/8/B1B1K3 w - - 0 1"), Color::White), 0);
+        assert_eq!(scale_factor(&board("8/8/8/3k4/8/8/8/BB2K3 w - - 0 1"), Color::White), SCALE_NORMAL);
+        // Opposite bishops, a pawn up.
+        let opposite = board("2b1k3/5p2/8/8/8/8/4PP2/2B1K3 w - - 0 1");
+        assert_eq!(scale_factor(&opposite, Color::White), SCALE_OPPOSITE_BISHOPS);
+        // A dark bishop cannot drive the king out of h8, a light one can.
+        let wrong = board("7k/8/8/7P/8/8/8/3BK3 w - - 0 1");
+        let right = board("7k/8/8/7P/8/8/8/2B1K3 w - - 0 1");
+        assert_eq!(scale_factor(&wrong, Color::White), 0);
+        assert_eq!(scale_factor(&right, Color::White), SCALE_NORMAL);
+    }
+}
@@ -2,14 +2,19 @@
 // not white or black is winning and assigning a numerical score to a specific
 // board.
 
+use crate::endgame::*;
+use crate::king_safety::*;
+use crate::movepick::*;
+use crate::pawns::*;
+use crate::pieces::*;
 use cozy_chess::*;
 
 pub type EvalInt = i32;
 
-type psqt = [EvalInt;64];
+type Psqt = [EvalInt; 64];
 
 // Piece Square Tables from the pov of White
-const pawn_pst : psqt = [
+const PAWN_PST: Psqt = [
     0,  0,  0,  0,  0,  0,  0,  0,
     50, 50, 50, 50, 50, 50, 50, 50,
     10, 10, 20, 30, 30, 20, 10, 10,
@@ -21,7 +26,7 @@ const pawn_pst : psqt = [
 ];
 
 // eg = end game
-const eg_pawn_pst: psqt = [
+const EG_PAWN_PST: Psqt = [
     0,   0,   0,   0,   0,   0,   0,   0,
     178, 173, 158, 134, 147, 132, 165, 187,
     94, 100,  85,  67,  56,  53,  82,  84,
@@ -32,7 +37,7 @@ const eg_pawn_pst: psqt = [
     0,   0,   0,   0,   0,   0,   0,   0,
 ];
 
-const knight_pst : psqt = [
+const KNIGHT_PST: Psqt = [
     -50,-40,-30,-30,-30,-30,-40,-50,
     -40,-20,  0,  0,  0,  0,-20,-40,
     -30,  0, 10, 15, 15, 10,  0,-30,
@@ -43,7 +48,7 @@ const knight_pst : psqt = [
     -50,-40,-30,-30,-30,-30,-40,-50,
 ];
 
-const eg_knight_pst : psqt = [
+const EG_KNIGHT_PST: Psqt = [
     -58, -38, -13, -28, -31, -27, -63, -99,
     -25,  -8, -25,  -2,  -9, -25, -24, -52,
     -24, -20,  10,   9,  -1,  -9, -19, -41,
@@ -54,7 +59,7 @@ const eg_knight_pst : psqt = [
     -29, -51, -23, -15, -22, -18, -50, -64,
 ];
 
-const bishop_pst : psqt = [
+const BISHOP_PST: Psqt = [
     -20,-10,-10,-10,-10,-10,-10,-20,
     -10,  0,  0,  0,  0,  0,  0,-10,
     -10,  0,  5, 10, 10,  5,  0,-10,
@@ -65,7 +70,7 @@ const bishop_pst : psqt = [
     -20,-10,-10,-10,-10,-10,-10,-20,
 ];
 
-const eg_bishop_pst : psqt = [
+const EG_BISHOP_PST: Psqt = [
     -14, -21, -11,  -8, -7,  -9, -17, -24,
     -8,  -4,   7, -12, -3, -13,  -4, -14,
     2,  -8,   0,  -1, -2,   6,   0,   4,
@@ -76,7 +81,7 @@ const eg_bishop_pst : psqt = [
     -23,  -9, -23,  -5, -9, -16,  -5, -17,
 ];
 
-const rook_pst : psqt = [
+const ROOK_PST: Psqt = [
     0,  0,  0,  0,  0,  0,  0,  0,
     5, 10, 10, 10, 10, 10, 10,  5,
     -5,  0,  0,  0,  0,  0,  0, -5,
@@ -87,7 +92,7 @@ const rook_pst : psqt = [
     0,  0,  0,  5,  5,  0,  0,  0
 ];
 
-const eg_rook_pst : psqt = [
+const EG_ROOK_PST: Psqt = [
     13, 10, 18, 15, 12,  12,   8,   5,
     11, 13, 13, 11, -3,   3,   8,   3,
     7,  7,  7,  5,  4,  -3,  -5,  -3,
@@ -98,7 +103,7 @@ const eg_rook_pst : psqt = [
     -9,  2,  3, -1, -5, -13,   4, -20,
 ];
 
-const queen_pst : psqt = [
+const QUEEN_PST: Psqt = [
     -20,-10,-10, -5, -5,-10,-10,-20,
     -10,  0,  0,  0,  0,  0,  0,-10,
     -10,  0,  5,  5,  5,  5,  0,-10,
@@ -109,7 +114,7 @@ const queen_pst : psqt = [
     -20,-10,-10, -5, -5,-10,-10,-20
 ];
 
-const eg_queen_pst : psqt = [
+const EG_QUEEN_PST: Psqt = [
     -9,  22,  22,  27,  27,  19,  10,  20,
     -17,  20,  32,  41,  58,  25,  30,   0,
     -20,   6,   9,  49,  47,  35,  19,   9,
@@ -120,7 +125,7 @@ const eg_queen_pst : psqt = [
     -33, -28, -22, -43,  -5, -32, -20, -41,
 ];
 
-const king_pst : psqt = [
+const KING_PST: Psqt = [
     -30,-40,-40,-50,-50,-40,-40,-30,
     -30,-40,-40,-50,-50,-40,-40,-30,
     -30,-40,-40,-50,-50,-40,-40,-30,
@@ -131,7 +136,7 @@ const king_pst : psqt = [
     20, 30, 10,  0,  0, 10, 30, 20
 ];
 
-const eg_king_pst : psqt = [
+const EG_KING_PST: Psqt = [
     -74, -35, -18, -18, -11,  15,   4, -17,
     -12,  17,  14,  17,  17,  38,  23,  11,
     10,  17,  23,  15,  20,  45,  44,  13,
@@ -142,91 +147,199 @@ const eg_king_pst : psqt = [
     -53, -34, -21, -11, -28, -14, -24, -43
 ];
 
-fn convertFile(file: File) -> usize {
-    return file as usize;
-}
+// Piece values, indexed by Piece. The king is never traded, so its value
+// does not matter.
+const MG_VALUES: [EvalInt; Piece::NUM] = [100, 250, 300, 500, 900, 0];
+pub const EG_VALUES: [EvalInt; Piece::NUM] = [130, 240, 300, 520, 920, 0];
 
-fn convertRank(rank: Rank) -> usize {
-    return rank as usize;
-}
+const MG_PSTS: [Psqt; Piece::NUM] = [PAWN_PST, KNIGHT_PST, BISHOP_PST, ROOK_PST, QUEEN_PST, KING_PST];
+const EG_PSTS: [Psqt; Piece::NUM] = [EG_PAWN_PST, EG_KNIGHT_PST, EG_BISHOP_PST, EG_ROOK_PST, EG_QUEEN_PST, EG_KING_PST];
 
-fn get_val(piece: Piece, index: usize, endgame: bool) -> EvalInt {
-    
-    if piece == Piece::Pawn {
-        if endgame {return 100 + eg_pawn_pst[index];}
-        return 100 + pawn_pst[index];
-    } 
-    if piece == Piece::Knight {
-        if endgame {return 250 + eg_knight_pst[index];}
-        return 250 + knight_pst[index];
-    }
-    if piece == Piece::Bishop {
-        if endgame {return 300 + eg_bishop_pst[index];}
-        return 300 + bishop_pst[index];
-    }
-    if piece == Piece::Rook {
-        if endgame {return 500 + eg_rook_pst[index];}
-        return 500 + rook_pst[index];
-    }
-    if piece == Piece::Queen {
-        if endgame {return 900 + eg_queen_pst[index];}
-        return 900 + queen_pst[index];
-    }
-    if piece == Piece::King {
-        if endgame {return 20000 + eg_king_pst[index];}
-        return 20000 + king_pst[index];
+// How much each piece counts towards the game phase. With every piece still
+// on the board the phase is MAX_PHASE, a pure middlegame; with only kings
+// and pawns left it is 0, a pure endgame.
+const PHASE_WEIGHTS: [EvalInt; Piece::NUM] = [0, 1, 1, 2, 4, 0];
+pub const MAX_PHASE: EvalInt = 24;
+
+// The tables are written from White's side with rank 8 on top, so White's
+// squares are flipped vertically to index them and Black's map directly.
+fn psqt_index(color: Color, square: Square) -> usize {
+    match color {
+        Color::White => square as usize ^ 56,
+        Color::Black => square as usize,
     }
-    return 0;
 }
 
-pub trait Eval {
-    fn eval(&self) -> EvalInt;
+// Blends a middlegame and an endgame score by the phase, so that the eval
+// changes smoothly as pieces come off instead of jumping at a cutoff.
+pub fn taper(mg: EvalInt, eg: EvalInt, phase: EvalInt) -> EvalInt {
+    (mg * phase + eg * (MAX_PHASE - phase)) / MAX_PHASE
+}
+
+/// The parts of the eval that only depend on which piece stands where:
+/// material and piece-square sums from White's side, and the phase. The
+/// search keeps one per ply and updates it with each move it plays, instead
+/// of adding up the whole board at every node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EvalState {
+    mg: EvalInt,
+    eg: EvalInt,
+    phase: EvalInt,
+    // Zobrist hash of the pawns alone, for the pawn hash table.
+    pawn_key: u64,
 }
 
-impl Eval for Board {
+impl EvalState {
+    // Adds up the state of a board from scratch.
+    pub fn new(board: &Board) -> Self {
+        let mut state = EvalState::default();
+        for color in Color::ALL {
+            for piece in Piece::ALL {
+                for square in board.colored_pieces(color, piece) {
+                    state.add(color, piece, square);
+                }
+            }
+        }
+        state
+    }
 
-    fn eval(&self) -> EvalInt {
-        let mut white_score = 0;
-        let mut black_score = 0;
+    fn add(&mut self, color: Color, piece: Piece, square: Square) {
+        let sign = if color == Color::White { 1 } else { -1 };
+        let index = psqt_index(color, square);
+        self.mg += sign * (MG_VALUES[piece as usize] + MG_PSTS[piece as usize][index]);
+        self.eg += sign * (EG_VALUES[piece as usize] + EG_PSTS[piece as usize][index]);
+        self.phase += PHASE_WEIGHTS[piece as usize];
+        if piece == Piece::Pawn {
+            self.pawn_key ^= pawn_key(color, square);
+        }
+    }
 
-        let mut endgame = false;
-        if self.occupied().len() <= 16 {
-            endgame = true
+    fn remove(&mut self, color: Color, piece: Piece, square: Square) {
+        let sign = if color == Color::White { 1 } else { -1 };
+        let index = psqt_index(color, square);
+        self.mg -= sign * (MG_VALUES[piece as usize] + MG_PSTS[piece as usize][index]);
+        self.eg -= sign * (EG_VALUES[piece as usize] + EG_PSTS[piece as usize][index]);
+        self.phase -= PHASE_WEIGHTS[piece as usize];
+        if piece == Piece::Pawn {
+            self.pawn_key ^= pawn_key(color, square);
         }
+    }
 
-        let bb = BitBoard::FULL;
-        for square in bb.iter() {
-            if self.color_on(square) == Some(Color::White) {
-                let index = convertFile(square.file()) * 8 + convertRank(square.rank());
-    
-                white_score += get_val(self.piece_on(square).unwrap(),index, endgame);
-            } else if self.color_on(square) == Some(Color::Black) {
-                let blackIndex = (7 - convertFile(square.file())) * 8 + (7 - convertRank(square.rank()));
-                black_score += get_val(self.piece_on(square).unwrap(), blackIndex, endgame);
-            }
+    // Updates the state for a move.
+    // Arguments: The board before the move is played, and the move
+    pub fn play(&mut self, board: &Board, mv: Move) {
+        let us = board.side_to_move();
+        let Some(piece) = board.piece_on(mv.from) else {
+            return;
+        };
+
+        // Castling is encoded as the king taking its own rook.
+        if board.colors(us).has(mv.to) {
+            let rank = mv.from.rank();
+            let (king_file, rook_file) = if mv.to.file() > mv.from.file() {
+                (File::G, File::F)
+            } else {
+                (File::C, File::D)
+            };
+            self.remove(us, Piece::King, mv.from);
+            self.remove(us, Piece::Rook, mv.to);
+            self.add(us, Piece::King, Square::new(king_file, rank));
+            self.add(us, Piece::Rook, Square::new(rook_file, rank));
+            return;
         }
 
-        let score = white_score - black_score;
-        if self.side_to_move() == Color::Black {
-            return score * -1;
+        if let Some(victim) = board.piece_on(mv.to) {
+            self.remove(!us, victim, mv.to);
+        } else if is_capture(board, mv) {
+            // En passant: the captured pawn is beside the target square.
+            self.remove(!us, Piece::Pawn, Square::new(mv.to.file(), mv.from.rank()));
         }
-        return score;
+        self.remove(us, piece, mv.from);
+        self.add(us, mv.promotion.unwrap_or(piece), mv.to);
     }
+}
 
+// Evaluates a board whose piece placement is already summed up in state.
+// Arguments: The board, its eval state and the thread's pawn hash table
+// Returns: The score from the side to move's point of view
+pub fn evaluate(board: &Board, state: &EvalState, pawn_table: &mut PawnTable) -> EvalInt {
+    if let Some(score) = evaluate_endgame(board) {
+        return score;
+    }
+    let (pawns_mg, pawns_eg) = evaluate_pawns(board, state.pawn_key, pawn_table);
+    let (king_mg, king_eg) = evaluate_king_safety(board);
+    let (pieces_mg, pieces_eg) = evaluate_pieces(board);
+    let mg = state.mg + pawns_mg + king_mg + pieces_mg;
+    let eg = state.eg + pawns_eg + king_eg + pieces_eg;
+    let score = taper(mg, eg, state.phase.min(MAX_PHASE));
+    let strong = if score > 0 { Color::White } else { Color::Black };
+    let score = score * scale_factor(board, strong) / SCALE_NORMAL;
+    match board.side_to_move() {
+        Color::White => score,
+        Color::Black => -score,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use cozy_chess::*;
     use crate::eval::*;
+
+    fn eval(board: &Board) -> EvalInt {
+        evaluate(board, &EvalState::new(board), &mut PawnTable::default())
+    }
+
     #[test]
     fn test_eval() {
         let board1 = Board::from_fen("rnbq1b2/4kQ2/4B3/1N6/1N6/P5P1/P5PP/R1B1R1K1 b - - 2 29", false).unwrap();
-        let eval1 = board1.eval();
+        let eval1 = eval(&board1);
         let board2 = Board::from_fen("8/8/8/8/7k/K1n5/8/8 b - - 0 81", false).unwrap();
-        let eval2 = board2.eval();
+        let eval2 = eval(&board2);
 
         assert!(eval1 < 0, "got eval {eval1}");
-        assert!(eval2 > 0, "got eval {eval2}");
+        // A lone knight cannot mate.
+        assert_eq!(eval2, 0);
+    }
+
+    #[test]
+    fn test_eval_is_symmetric() {
+        assert_eq!(eval(&Board::default()), 0);
+        // The same position with the colours swapped.
+        let white = Board::from_fen("r3k2r/pp1n1ppp/2p1b3/q7/3P4/2N2N2/PP3PPP/R2QK2R w KQkq - 0 1", false).unwrap();
+        let black = Board::from_fen("r2qk2r/pp3ppp/2n2n2/3p4/Q7/2P1B3/PP1N1PPP/R3K2R b KQkq - 0 1", false).unwrap();
+        assert_eq!(eval(&white), eval(&black));
+    }
+
+    #[test]
+    fn test_incremental_state() {
+        // Castling both ways, en passant, promotions with and without a
+        // capture, and ordinary moves and captures.
+        let fens = [
+            "r3k2r/pPppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/P1PBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/Pp2P3/2N2Q1p/1PPBBPPP/R3K2R b KQkq a3 0 1",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        ];
+        for fen in fens {
+            let board = Board::from_fen(fen, false).unwrap();
+            let state = EvalState::new(&board);
+            board.generate_moves(|moves| {
+                for mv in moves {
+                    let mut child = board.clone();
+                    child.play(mv);
+                    let mut child_state = state;
+                    child_state.play(&board, mv);
+                    assert_eq!(child_state, EvalState::new(&child), "{fen} {mv}");
+                }
+                false
+            });
+        }
+    }
+
+    #[test]
+    fn test_phase() {
+        assert_eq!(EvalState::new(&Board::default()).phase, MAX_PHASE);
+        let kings_and_pawns = Board::from_fen("4k3/pppp4/8/8/8/8/4PPPP/4K3 w - - 0 1", false).unwrap();
+        assert_eq!(EvalState::new(&kings_and_pawns).phase, 0);
+        assert_eq!(taper(100, 200, MAX_PHASE / 2), 150);
     }
 }
\ No newline at end of file
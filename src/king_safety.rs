@@ -0,0 +1,152 @@
+// This file evaluates how safe each king is: how many enemy pieces attack
+// the squares around it, whether its pawn shield still stands, whether enemy
+// pawns are storming towards it, and whether the files near it are open to
+// enemy rooks. King safety matters while there is enough material left to
+// mate with, so these terms are almost all middlegame terms and fade out of
+// the tapered eval as pieces come off.
+
+use crate::eval::*;
+use crate::pawns::forward_ranks;
+use crate::pieces::attacks;
+use cozy_chess::*;
+
+// Attack units each kind of piece adds for every attack on the king zone,
+// indexed by Piece.
+const ATTACK_WEIGHTS: [EvalInt; Piece::NUM] = [0, 2, 2, 3, 5, 0];
+
+// Danger grows with the square of the attack units, as attacks by several
+// pieces at once are much more than the sum of their parts. A lone attacker
+// is no danger.
+const DANGER_DIVISOR: EvalInt = 4;
+const MAX_DANGER: EvalInt = 500;
+
+// Bonus for the king's own pawn closest in front of it on each file beside or
+// in front of the king, by the pawn's relative rank, and the penalty when
+// there is no such pawn.
+const SHIELD: [EvalInt; 8] = [0, 20, 10, 5, 0, 0, 0, 0];
+const MISSING_SHIELD: EvalInt = -20;
+
+// Penalty for the closest enemy pawn advancing on each of those files, by
+// its rank relative to the king's side.
+const STORM: [EvalInt; 8] = [0, 0, -30, -20, -10, 0, 0, 0];
+
+// Penalties for each of those files without pawns of the king's side, and
+// extra if it has no pawns at all.
+const SEMI_OPEN_FILE: EvalInt = -15;
+const OPEN_FILE: EvalInt = -10;
+
+// The squares around the king, plus the three squares further in front of
+// it where attacks on the shield are prepared.
+fn king_zone(color: Color, king: Square) -> BitBoard {
+    let around = get_king_moves(king) | king.bitboard();
+    let front = match color {
+        Color::White => BitBoard(around.0 << 8),
+        Color::Black => BitBoard(around.0 >> 8),
+    };
+    around | front
+}
+
+// The square of a set closest to color's side of the board.
+fn nearest(color: Color, set: BitBoard) -> Option<Square> {
+    if set.is_empty() {
+        return None;
+    }
+    match color {
+        Color::White => set.next_square(),
+        Color::Black => Some(Square::index(63 - set.0.leading_zeros() as usize)),
+    }
+}
+
+// Scores the attacks of color's enemies on color's king zone.
+fn king_attacks(board: &Board, color: Color, king: Square) -> (EvalInt, EvalInt) {
+    let zone = king_zone(color, king);
+    let occupied = board.occupied();
+    let mut units = 0;
+    let mut attackers = 0;
+    for piece in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
+        for square in board.colored_pieces(!color, piece) {
+            let hits = (attacks(piece, square, !color, occupied) & zone).len() as EvalInt;
+            if hits > 0 {
+                attackers += 1;
+                units += hits * ATTACK_WEIGHTS[piece as usize];
+            }
+        }
+    }
+    if attackers < 2 {
+        return (0, 0);
+    }
+    let danger = (units * units / DANGER_DIVISOR).min(MAX_DANGER);
+    (-danger, -units)
+}
+
+// Scores the pawns and open files around color's king.
+fn king_shelter(board: &Board, color: Color, king: Square) -> EvalInt {
+    let ours = board.colored_pieces(color, Piece::Pawn);
+    let theirs = board.colored_pieces(!color, Piece::Pawn);
+    let ahead = forward_ranks(color, king.rank());
+    let mut score = 0;
+    for file in File::ALL {
+        if (file as i32 - king.file() as i32).abs() > 1 {
+            continue;
+        }
+        let on_file = file.bitboard();
+        match nearest(color, ours & on_file & ahead) {
+            Some(pawn) => score += SHIELD[pawn.rank().relative_to(color) as usize],
+            None => score += MISSING_SHIELD,
+        }
+        if let Some(pawn) = nearest(color, theirs & on_file & ahead) {
+            score += STORM[pawn.rank().relative_to(color) as usize];
+        }
+        if (ours & on_file).is_empty() {
+            score += SEMI_OPEN_FILE;
+            if (theirs & on_file).is_empty() {
+                score += OPEN_FILE;
+            }
+        }
+    }
+    score
+}
+
+// Evaluates the safety of both kings.
+// Returns: The middlegame and endgame scores from White's side
+pub fn evaluate_king_safety(board: &Board) -> (EvalInt, EvalInt) {
+    let mut mg = 0;
+    let mut eg = 0;
+    for color in Color::ALL {
+        let sign = if color == Color::White { 1 } else { -1 };
+        let king = board.king(color);
+        let (attacks_mg, attacks_eg) = king_attacks(board, color, king);
+        mg += sign * (attacks_mg + king_shelter(board, color, king));
+        eg += sign * attacks_eg;
+    }
+    (mg, eg)
+}
+
+#[cfg(test)]
+mod tests {
+    use cozy_chess::*;
+    use crate::king_safety::*;
+
+    #[test]
+    fn test_shelter() {
+        // A castled king behind its three pawns, and the same king after
+        // pushing the g-pawn and losing the h-pawn.
+        let intact = Board::from_fen("4k3/8/8/8/8/8/5PPP/6K1 w - - 0 1", false).unwrap();
+        let broken = Board::from_fen("4k3/8/8/8/8/6P1/5P2/6K1 w - - 0 1", false).unwrap();
+        let king = Square::G1;
+        assert_eq!(king_shelter(&intact, Color::White, king), 3 * SHIELD[1]);
+        assert_eq!(
+            king_shelter(&broken, Color::White, king),
+            SHIELD[1] + SHIELD[2] + MISSING_SHIELD + SEMI_OPEN_FILE + OPEN_FILE
+        );
+    }
+
+    #[test]
+    fn test_attacks() {
+        // The queen alone does not count; with the knight it does.
+        let queen = Board::from_fen("6k1/5ppp/8/8/8/3Q4/5PPP/6K1 b - - 0 1", false).unwrap();
+        let both = Board::from_fen("6k1/5ppp/8/6N1/8/3Q4/5PPP/6K1 b - - 0 1", false).unwrap();
+        assert_eq!(king_attacks(&queen, Color::Black, Square::G8), (0, 0));
+        assert!(king_attacks(&both, Color::Black, Square::G8).0 < 0);
+    }
+}
@@ -0,0 +1,221 @@
+// This file holds a bitbase for king and pawn against king: for every
+// position, whether the side with the pawn wins. It is worked out once, the
+// first time it is needed, by retrograde analysis. Positions that are won or
+// drawn straight away are marked first, such as a pawn about to promote
+// safely or a pawn the defending king can take. Then every other position
+// is classified from the positions its moves lead to, over and over, until
+// nothing changes. Whatever is left undecided then is a draw.
+//
+// The bitbase is stored from the point of view of White having the pawn;
+// positions where Black has it are looked up with the ranks flipped.
+
+use cozy_chess::*;
+use std::sync::OnceLock;
+
+// Side to move, strong king, weak king and pawn squares.
+const POSITIONS: usize = 2 * Square::NUM * Square::NUM * Square::NUM;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Unknown,
+    Invalid,
+    Draw,
+    Win,
+}
+
+// One bit per position, set where the strong side wins.
+static BITBASE: OnceLock<Vec<u64>> = OnceLock::new();
+
+fn index(strong_to_move: bool, strong_king: Square, weak_king: Square, pawn: Square) -> usize {
+    (((strong_to_move as usize * Square::NUM + strong_king as usize) * Square::NUM) + weak_king as usize) * Square::NUM
+        + pawn as usize
+}
+
+// Marks the positions whose outcome is known without looking at any moves.
+fn initial_outcome(strong_to_move: bool, strong_king: Square, weak_king: Square, pawn: Square) -> Outcome {
+    let rank = pawn.rank();
+    if rank == Rank::First
+        || rank == Rank::Eighth
+        || strong_king == weak_king
+        || strong_king == pawn
+        || weak_king == pawn
+        || get_king_moves(strong_king).has(weak_king)
+    {
+        return Outcome::Invalid;
+    }
+    let pawn_attacks = get_pawn_attacks(pawn, Color::White);
+    if strong_to_move {
+        // The weak king cannot have been left in check.
+        if pawn_attacks.has(weak_king) {
+            return Outcome::Invalid;
+        }
+        // The pawn promotes and the new queen cannot be taken.
+        let promotion = Square::new(pawn.file(), Rank::Eighth);
+        if rank == Rank::Seventh
+            && promotion != strong_king
+            && promotion != weak_king
+            && (!get_king_moves(weak_king).has(promotion) || get_king_moves(strong_king).has(promotion))
+        {
+            return Outcome::Win;
+        }
+    } else {
+        let guarded = get_king_moves(strong_king) | pawn_attacks;
+        let escapes = get_king_moves(weak_king) & !guarded;
+        // Stalemate, or the pawn can be taken.
+        if (escapes.is_empty() && !pawn_attacks.has(weak_king)) || escapes.has(pawn) {
+            return Outcome::Draw;
+        }
+    }
+    Outcome::Unknown
+}
+
+// Classifies a position from the outcomes of the positions its moves lead
+// to. The strong side wins if any of its moves wins and draws if all of
+// them draw; the weak side draws if any of its moves draws and loses if all
+// of them lose.
+fn classify(outcomes: &[Outcome], strong_to_move: bool, strong_king: Square, weak_king: Square, pawn: Square) -> Outcome {
+    let mut any_win = false;
+    let mut any_draw = false;
+    let mut any_unknown = false;
+    let mut record = |outcome: Outcome| match outcome {
+        Outcome::Win => any_win = true,
+        Outcome::Draw => any_draw = true,
+        Outcome::Unknown => any_unknown = true,
+        Outcome::Invalid => {}
+    };
+
+    if strong_to_move {
+        for square in get_king_moves(strong_king) {
+            record(outcomes[index(false, square, weak_king, pawn)]);
+        }
+        // Promotions are only counted as wins by initial_outcome.
+        if pawn.rank() < Rank::Seventh {
+            let push = pawn.offset(0, 1);
+            if push != strong_king && push != weak_king {
+                record(outcomes[index(false, strong_king, weak_king, push)]);
+                if pawn.rank() == Rank::Second {
+                    let double = push.offset(0, 1);
+                    if double != strong_king && double != weak_king {
+                        record(outcomes[index(false, strong_king, weak_king, double)]);
+                    }
+                }
+            }
+        }
+        if any_win {
+            Outcome::Win
+        } else if any_unknown {
+            Outcome::Unknown
+        } else {
+            Outcome::Draw
+        }
+    } else {
+        for square in get_king_moves(weak_king) {
+            record(outcomes[index(true, strong_king, square, pawn)]);
+        }
+        if any_draw {
+            Outcome::Draw
+        } else if any_unknown {
+            Outcome::Unknown
+        } else {
+            Outcome::Win
+        }
+    }
+}
+
+fn generate() -> Vec<u64> {
+    let mut outcomes = vec![Outcome::Unknown; POSITIONS];
+    for (i, outcome) in outcomes.iter_mut().enumerate() {
+        let pawn = Square::index(i % Square::NUM);
+        let weak_king = Square::index(i / Square::NUM % Square::NUM);
+        let strong_king = Square::index(i / (Square::NUM * Square::NUM) % Square::NUM);
+        *outcome = initial_outcome(i >= POSITIONS / 2, strong_king, weak_king, pawn);
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..POSITIONS {
+            if outcomes[i] != Outcome::Unknown {
+                continue;
+            }
+            let pawn = Square::index(i % Square::NUM);
+            let weak_king = Square::index(i / Square::NUM % Square::NUM);
+            let strong_king = Square::index(i / (Square::NUM * Square::NUM) % Square::NUM);
+            let outcome = classify(&outcomes, i >= POSITIONS / 2, strong_king, weak_king, pawn);
+            if outcome != Outcome::Unknown {
+                outcomes[i] = outcome;
+                changed = true;
+            }
+        }
+    }
+
+    let mut bits = vec![0; POSITIONS / 64];
+    for (i, &outcome) in outcomes.iter().enumerate() {
+        if outcome == Outcome::Win {
+            bits[i / 64] |= 1 << (i % 64);
+        }
+    }
+    bits
+}
+
+// Works out the bitbase now rather than in the middle of the first search
+// that needs it.
+pub fn init() {
+    BITBASE.get_or_init(generate);
+}
+
+// Looks up whether the side with the pawn wins a king and pawn against king
+// position.
+// Arguments: The side with the pawn, the two kings, the pawn, and whether the
+// side with the pawn is to move
+// Returns: Whether the side with the pawn wins
+pub fn probe(strong: Color, strong_king: Square, weak_king: Square, pawn: Square, strong_to_move: bool) -> bool {
+    let (strong_king, weak_king, pawn) = match strong {
+        Color::White => (strong_king, weak_king, pawn),
+        Color::Black => (strong_king.flip_rank(), weak_king.flip_rank(), pawn.flip_rank()),
+    };
+    let i = index(strong_to_move, strong_king, weak_king, pawn);
+    let bits = BITBASE.get_or_init(generate);
+    bits[i / 64] & (1 << (i % 64)) != 0
+}
+
+// Looks the board up in the bitbase if only the two kings and one pawn are
+// left on it.
+// Returns: Whether the side with the pawn wins, or None if the board is not
+// king and pawn against king
+pub fn probe_board(board: &Board) -> Option<bool> {
+    let pawns = board.pieces(Piece::Pawn);
+    if pawns.len() != 1 || board.occupied().len() != 3 {
+        return None;
+    }
+    let pawn = pawns.next_square()?;
+    let strong = board.color_on(pawn)?;
+    Some(probe(strong, board.king(strong), board.king(!strong), pawn, board.side_to_move() == strong))
+}
+
+#[cfg(test)]
+mod tests {
+    use cozy_chess::*;
+    use crate::kpk::*;
+
+    fn probe_fen(fen: &str) -> Option<bool> {
+        probe_board(&Board::from_fen(fen, false).unwrap())
+    }
+
+    #[test]
+    fn test_kpk() {
+        // The strong king in front of its pawn wins if it has the
+        // opposition and draws if the defender has it.
+        assert_eq!(probe_fen("8/4k3/8/4K3/4P3/8/8/8 b - - 0 1"), Some(true));
+        assert_eq!(probe_fen("8/4k3/8/4K3/4P3/8/8/8 w - - 0 1"), Some(false));
+        // The defender reaches the corner in front of a rook pawn.
+        assert_eq!(probe_fen("7k/8/5K2/7P/8/8/8/8 w - - 0 1"), Some(false));
+        // The pawn runs away from the defending king.
+        assert_eq!(probe_fen("8/8/8/8/P7/8/7k/K7 w - - 0 1"), Some(true));
+        // The same positions with the colours swapped.
+        assert_eq!(probe_fen("8/8/8/4p3/4k3/8/4K3/8 w - - 0 1"), Some(true));
+        assert_eq!(probe_fen("8/8/8/4p3/4k3/8/4K3/8 b - - 0 1"), Some(false));
+        // Not king and pawn against king.
+        assert_eq!(probe_fen("8/4k3/8/4K3/4P3/4P3/8/8 b - - 0 1"), None);
+    }
+}
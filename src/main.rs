@@ -10,16 +10,25 @@ python3 lichess-bot.py
 use cozy_chess::*;
 use cozy_chess::util::*;
 use std::io;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread::{self, JoinHandle};
+use crate::options::*;
 use crate::search::*;
+mod endgame;
 mod eval;
+mod king_safety;
+mod kpk;
+mod movepick;
+mod options;
+mod pawns;
+mod pieces;
 mod search;
+mod see;
+mod tt;
 pub type EvalInt = i32;
 
 
-// 12 is the number of bytes in the posInfo struct entry
-// 2^20 is the number of bytes in one binary megabyte
-const magicConstant : usize = (1 << 20) / 12; 
-
 macro_rules! ignore {
     () => {
         continue
@@ -31,20 +40,79 @@ macro_rules! ignore {
 // Arguments: None
 // Returns: None
 fn cmd_uci() -> String {
-    let str = "id name gleam\n\
-               id author rain\n\
-               uciok";
-    str.into()
+    let mut str = String::from("id name gleam\n\
+                                id author rain\n");
+    for option in OPTIONS {
+        str.push_str(&option.declaration());
+        str.push('\n');
+    }
+    str.push_str("uciok");
+    str
+}
+
+// Changes one of the options declared in OPTIONS. Problems are reported to
+// the GUI as info strings rather than stopping the engine.
+// Arguments: Tokens, the current options and the transposition table
+// Returns: None
+fn cmd_setoption(
+    tokens: std::str::SplitWhitespace<'_>,
+    options: &mut EngineOptions,
+    transposition_table: &mut Arc<tt::HashTable>,
+) {
+    let Some((name, value)) = parse_setoption(tokens) else {
+        println!("info string setoption is missing a name");
+        return;
+    };
+    let Some(option) = find_option(&name) else {
+        println!("info string unknown option '{}'", name);
+        return;
+    };
+    let value = match option.parse_value(value.as_deref()) {
+        Ok(value) => value,
+        Err(message) => {
+            println!("info string {}", message);
+            return;
+        }
+    };
+
+    match (option.name, value) {
+        ("Hash", OptionValue::Spin(mib)) => {
+            options.hash_mib = mib as usize;
+            *transposition_table = Arc::new(tt::HashTable::new(options.hash_mib));
+        }
+        ("Clear Hash", OptionValue::Button) => {
+            transposition_table.clear();
+        }
+        ("Threads", OptionValue::Spin(threads)) => {
+            options.threads = threads as usize;
+        }
+        ("Move Overhead", OptionValue::Spin(ms)) => {
+            options.move_overhead = ms as u64;
+        }
+        ("Ponder", OptionValue::Check(ponder)) => {
+            options.ponder = ponder;
+        }
+        ("MultiPV", OptionValue::Spin(lines)) => {
+            options.multipv = lines as usize;
+        }
+        ("Contempt", OptionValue::Spin(contempt)) => {
+            options.contempt = contempt as i32;
+        }
+        _ => unreachable!("option {} was declared but not handled", option.name),
+    }
 }
 
 // Loads the board with moves
 // Arguments: Takes in the tokens from the terminal and the board
-// Returns: The board state with the moves being made.
-fn cmd_position_moves(mut tokens: std::str::SplitWhitespace<'_>, mut board: Board) -> Board {
+// Returns: The board state with the moves being made, and the hashes of the
+// positions before it, oldest first, for spotting repetitions.
+fn cmd_position_moves(mut tokens: std::str::SplitWhitespace<'_>, mut board: Board) -> (Board, Vec<u64>) {
+    let mut history = Vec::new();
     while let Some(token) = tokens.next() {
         match token {
             "moves" => {
                 for mv in tokens.by_ref() {
+                    history.push(board.hash());
                     board.play(parse_uci_move(&board,mv).unwrap());
                     
                 }
@@ -53,13 +121,13 @@ fn cmd_position_moves(mut tokens: std::str::SplitWhitespace<'_>, mut board: Boar
         }
     }
 
-    board
+    (board, history)
 }
 
 // Sets the position of a board.
 // Arguments: Tokens
-// Returns: Board
-fn set_position(mut tokens: std::str::SplitWhitespace<'_>) -> Board {
+// Returns: Board and the hashes of the positions played before it
+fn set_position(mut tokens: std::str::SplitWhitespace<'_>) -> (Board, Vec<u64>) {
     while let Some(token) = tokens.next() {
         match token {
             "fen" => {
@@ -72,16 +140,13 @@ fn set_position(mut tokens: std::str::SplitWhitespace<'_>) -> Board {
                     }
                 }
 
-                let mut board = Board::from_fen(&fen,false)
+                let board = Board::from_fen(&fen,false)
                     .unwrap_or_else(|e| panic!("failed to parse fen '{fen}': {e:?}"));
-                board = cmd_position_moves(tokens,board);
-
-                return board;
+                return cmd_position_moves(tokens,board);
             }
             "startpos" => {
-                let mut board = Board::default();
-                board = cmd_position_moves(tokens,board);
-                return board;
+                let board = Board::default();
+                return cmd_position_moves(tokens,board);
             }
             _ => ignore!(),
         }
@@ -89,35 +154,100 @@ fn set_position(mut tokens: std::str::SplitWhitespace<'_>) -> Board {
     panic!("position command was empty")
 }
 
-// Function for doing a move. Prints to terminal the move the computer chooses
-// Arguments: Tokens and a board
-// Returns: None
-fn cmd_go(mut _tokens: std::str::SplitWhitespace<'_>, board: &mut Board, transpositionTable: &mut search::HashTable) {
-    let mut vec = Vec::new();
+// Reads the limits that follow a go command, e.g.
+// "wtime 60000 btime 60000 winc 1000 binc 1000 movestogo 40".
+// Arguments: Tokens
+// Returns: The search limits, with anything missing or malformed left unset
+fn parse_go(mut tokens: std::str::SplitWhitespace<'_>) -> SearchLimits {
+    let mut limits = SearchLimits::default();
+    while let Some(token) = tokens.next() {
+        match token {
+            "wtime" => limits.wtime = parse_time(tokens.next()),
+            "btime" => limits.btime = parse_time(tokens.next()),
+            "winc" => limits.winc = parse_time(tokens.next()),
+            "binc" => limits.binc = parse_time(tokens.next()),
+            "movestogo" => limits.movestogo = tokens.next().and_then(|t| t.parse().ok()),
+            "movetime" => limits.movetime = parse_time(tokens.next()),
+            "depth" => limits.depth = tokens.next().and_then(|t| t.parse().ok()),
+            "nodes" => limits.nodes = tokens.next().and_then(|t| t.parse().ok()),
+            "infinite" => limits.infinite = true,
+            "ponder" => limits.ponder = true,
+            _ => ignore!(),
+        }
+    }
+    limits
+}
 
-    board.generate_moves(|moves| {
-        for _mv in moves {
-            vec.push(_mv);
+// Some GUIs send a negative clock once a player has flagged, so those are
+// clamped to zero rather than rejected.
+fn parse_time(token: Option<&str>) -> Option<u64> {
+    token?.parse::<i64>().ok().map(|ms| ms.max(0) as u64)
+}
+
+// Starts searching for a move on a new thread. The thread prints the move the
+// computer chooses once the search finishes or is stopped.
+// Arguments: Tokens, a board, the hashes of the positions played before it,
+// the options, the transposition table and the search signals
+// Returns: The handle of the search thread
+fn cmd_go(
+    tokens: std::str::SplitWhitespace<'_>,
+    board: &Board,
+    history: &[u64],
+    options: &EngineOptions,
+    transposition_table: &Arc<tt::HashTable>,
+    signals: &Arc<SearchSignals>,
+) -> JoinHandle<()> {
+    let limits = parse_go(tokens);
+    let options = options.clone();
+    let mut board = board.clone();
+    let history = history.to_vec();
+    let transposition_table = Arc::clone(transposition_table);
+    let signals = Arc::clone(signals);
+    signals.stop.store(false, Ordering::Relaxed);
+    signals.ponder.store(limits.ponder, Ordering::Relaxed);
+
+    thread::spawn(move || {
+        match best_move(&mut board, &history, &transposition_table, &limits, &options, &signals) {
+            Some((chosen, Some(ponder))) if options.ponder => {
+                let thing = display_uci_move(&board, chosen);
+                board.play(chosen);
+                println!("bestmove {} ponder {}", thing, display_uci_move(&board, ponder));
+            }
+            Some((chosen, _)) => println!("bestmove {}", display_uci_move(&board, chosen)),
+            None => println!("bestmove 0000"),
         }
-        false
-    });
-    
-    let chosen = best_move(board, transpositionTable);
+    })
+}
 
-    let thing = display_uci_move(&board,chosen);
-    println!("bestmove {}", thing);
+// Tells a running search to finish and waits for it to print its move.
+// Arguments: The handle of the search thread, if any, and the search signals
+// Returns: None
+fn cmd_stop(search_thread: &mut Option<JoinHandle<()>>, signals: &SearchSignals) {
+    if let Some(handle) = search_thread.take() {
+        signals.stop.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+    }
 }
 
 
 
 fn main() {
     let stdin = io::stdin();
+    kpk::init();
     
     let mut board = Board::default();
-    let mut transpositionTable = search::HashTable::default();
+    let mut history: Vec<u64> = Vec::new();
+    let mut options = EngineOptions::default();
+    let mut transposition_table = Arc::new(tt::HashTable::new(options.hash_mib));
+    let signals = Arc::new(SearchSignals::default());
+    let mut search_thread: Option<JoinHandle<()>> = None;
     loop {
         let mut line = String::new();
-        stdin.read_line(&mut line).unwrap();
+        if stdin.read_line(&mut line).unwrap() == 0 {
+            // The GUI closed our input, which is as good as quit.
+            cmd_stop(&mut search_thread, &signals);
+            return;
+        }
         let mut tokens = line.split_whitespace();
         while let Some(token) = tokens.next() {
             match token {
@@ -128,16 +258,36 @@ fn main() {
                     println!("readyok");
                 }
                 "ucinewgame" => {
+                    cmd_stop(&mut search_thread, &signals);
+                    // Nothing learned about the last game is any use in the
+                    // next one.
+                    transposition_table.clear();
                     board = Board::default();
+                    history.clear();
                 }
                 "quit" => {
+                    cmd_stop(&mut search_thread, &signals);
                     return;
                 }
+                "stop" => {
+                    cmd_stop(&mut search_thread, &signals);
+                }
+                "ponderhit" => {
+                    // The opponent played the move we pondered on, so the
+                    // search carries on with our clock running.
+                    signals.ponder.store(false, Ordering::Relaxed);
+                }
+                "setoption" => {
+                    cmd_stop(&mut search_thread, &signals);
+                    cmd_setoption(tokens, &mut options, &mut transposition_table);
+                }
                 "position" => {
-                    board = set_position(tokens);
+                    cmd_stop(&mut search_thread, &signals);
+                    (board, history) = set_position(tokens);
                 }
                 "go" => {
-                    cmd_go(tokens, &mut board, &mut transpositionTable);
+                    cmd_stop(&mut search_thread, &signals);
+                    search_thread = Some(cmd_go(tokens, &board, &history, &options, &transposition_table, &signals));
                 }
                 _ => ignore!(),
             }
@@ -146,3 +296,29 @@ fn main() {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_parse_go() {
+        let limits = parse_go("wtime 60000 btime -150 winc 1000 movestogo 12 depth 9".split_whitespace());
+        assert_eq!(limits.wtime, Some(60000));
+        // A flagged clock can go negative, which counts as no time left.
+        assert_eq!(limits.btime, Some(0));
+        assert_eq!(limits.winc, Some(1000));
+        assert_eq!(limits.binc, None);
+        assert_eq!(limits.movestogo, Some(12));
+        assert_eq!(limits.depth, Some(9));
+        assert!(!limits.infinite && !limits.ponder);
+
+        let limits = parse_go("infinite".split_whitespace());
+        assert!(limits.infinite);
+        assert_eq!(limits.movetime, None);
+
+        let limits = parse_go("ponder movetime 500 nodes lots".split_whitespace());
+        assert!(limits.ponder);
+        assert_eq!(limits.movetime, Some(500));
+        assert_eq!(limits.nodes, None);
+    }
+}
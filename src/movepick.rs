@@ -0,0 +1,280 @@
+// This file decides the order in which the search tries moves. Alpha-beta
+// prunes the most when the best move comes first, so moves are handed out in
+// stages, from the likeliest to refute a position to the least likely:
+//   1. the move the transposition table remembers for this position,
+//   2. captures that do not lose material by static exchange, most valuable
+//      victim first and least valuable attacker second,
+//   3. killer moves, quiet moves that caused a cutoff at the same ply,
+//   4. all other quiet moves, by how often they caused cutoffs before,
+//   5. captures that lose material by static exchange.
+
+use crate::see::*;
+use cozy_chess::*;
+
+// History scores are kept within +-MAX_HISTORY.
+const MAX_HISTORY: i32 = 16384;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    TtMove,
+    GenerateMoves,
+    GoodCaptures,
+    Killers,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+// Whether a move takes a piece, including en passant.
+pub fn is_capture(board: &Board, mv: Move) -> bool {
+    if board.colors(!board.side_to_move()).has(mv.to) {
+        return true;
+    }
+    board.piece_on(mv.from) == Some(Piece::Pawn) && mv.from.file() != mv.to.file()
+}
+
+// Quiet moves neither capture nor promote. Only they are scored by the
+// killer and history heuristics.
+pub fn is_quiet(board: &Board, mv: Move) -> bool {
+    mv.promotion.is_none() && !is_capture(board, mv)
+}
+
+/// The butterfly history table: for each side and each from and to square, a
+/// score of how often that move caused a beta cutoff.
+pub struct History {
+    table: Box<[[[i32; Square::NUM]; Square::NUM]; Color::NUM]>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            table: Box::new([[[0; Square::NUM]; Square::NUM]; Color::NUM]),
+        }
+    }
+}
+
+impl History {
+    pub fn get(&self, color: Color, mv: Move) -> i32 {
+        self.table[color as usize][mv.from as usize][mv.to as usize]
+    }
+
+    // Rewards a move that caused a cutoff at the given depth, or punishes one
+    // that was tried before the cutoff and failed. Scores move towards the
+    // bonus in proportion to how far they are from it, so they stay bounded
+    // and old information fades.
+    pub fn update(&mut self, color: Color, mv: Move, depth: usize, good: bool) {
+        let bonus = ((depth * depth) as i32).min(MAX_HISTORY);
+        let bonus = if good { bonus } else { -bonus };
+        let entry = &mut self.table[color as usize][mv.from as usize][mv.to as usize];
+        *entry += bonus - *entry * bonus.abs() / MAX_HISTORY;
+    }
+}
+
+pub struct MovePicker {
+    stage: Stage,
+    tt_move: Option<Move>,
+    killers: [Option<Move>; 2],
+    killer_index: usize,
+    captures_only: bool,
+    good_captures: Vec<(Move, i32)>,
+    quiets: Vec<(Move, i32)>,
+    bad_captures: Vec<(Move, i32)>,
+}
+
+impl MovePicker {
+    // A picker for every legal move of a position in the main search.
+    pub fn new(tt_move: Option<Move>, killers: [Option<Move>; 2]) -> Self {
+        MovePicker {
+            stage: Stage::TtMove,
+            tt_move,
+            killers,
+            killer_index: 0,
+            captures_only: false,
+            good_captures: Vec::new(),
+            quiets: Vec::new(),
+            bad_captures: Vec::new(),
+        }
+    }
+
+    // A picker for the captures and queen promotions of a position only, for
+    // quiescence search. Captures that lose material and underpromotions are
+    // left out altogether.
+    pub fn new_captures() -> Self {
+        MovePicker {
+            stage: Stage::GenerateMoves,
+            tt_move: None,
+            killers: [None; 2],
+            killer_index: 0,
+            captures_only: true,
+            good_captures: Vec::new(),
+            quiets: Vec::new(),
+            bad_captures: Vec::new(),
+        }
+    }
+
+    // Hands out the next move to search, or None once every move is done.
+    pub fn next(&mut self, board: &Board, history: &History) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::TtMove => {
+                    self.stage = Stage::GenerateMoves;
+                    if let Some(mv) = self.tt_move
+                        && board.is_legal(mv)
+                    {
+                        return Some(mv);
+                    }
+                    self.tt_move = None;
+                }
+                Stage::GenerateMoves => {
+                    self.generate(board, history);
+                    self.stage = Stage::GoodCaptures;
+                }
+                Stage::GoodCaptures => {
+                    if let Some(mv) = pick_best(&mut self.good_captures) {
+                        return Some(mv);
+                    }
+                    self.stage = if self.captures_only { Stage::Done } else { Stage::Killers };
+                }
+                Stage::Killers => {
+                    while self.killer_index < self.killers.len() {
+                        let killer = self.killers[self.killer_index];
+                        self.killer_index += 1;
+                        // A killer comes from a sibling position, so it has to
+                        // be checked against this one.
+                        if let Some(mv) = killer
+                            && Some(mv) != self.tt_move
+                            && let Some(i) = self.quiets.iter().position(|&(quiet, _)| quiet == mv)
+                        {
+                            self.quiets.swap_remove(i);
+                            return Some(mv);
+                        }
+                    }
+                    self.stage = Stage::Quiets;
+                }
+                Stage::Quiets => {
+                    if let Some(mv) = pick_best(&mut self.quiets) {
+                        return Some(mv);
+                    }
+                    self.stage = Stage::BadCaptures;
+                }
+                Stage::BadCaptures => {
+                    if let Some(mv) = pick_best(&mut self.bad_captures) {
+                        return Some(mv);
+                    }
+                    self.stage = Stage::Done;
+                }
+                Stage::Done => return None,
+            }
+        }
+    }
+
+    // Sorts the position's legal moves into scored captures and quiets,
+    // leaving out the transposition table move, which was already tried.
+    fn generate(&mut self, board: &Board, history: &History) {
+        let color = board.side_to_move();
+        board.generate_moves(|moves| {
+            for mv in moves {
+                if Some(mv) == self.tt_move {
+                    continue;
+                }
+                if !is_quiet(board, mv) {
+                    if self.captures_only && mv.promotion.is_some_and(|piece| piece != Piece::Queen) {
+                        continue;
+                    }
+                    if see_ge(board, mv, 0) {
+                        self.good_captures.push((mv, mvv_lva(board, mv)));
+                    } else if !self.captures_only {
+                        self.bad_captures.push((mv, mvv_lva(board, mv)));
+                    }
+                } else if !self.captures_only {
+                    self.quiets.push((mv, history.get(color, mv)));
+                }
+            }
+            false
+        });
+    }
+}
+
+// Scores a capture or promotion by most valuable victim, then least valuable
+// attacker. A king only takes pieces that are not defended, so it counts as
+// the cheapest attacker.
+fn mvv_lva(board: &Board, mv: Move) -> i32 {
+    let victim = captured_value(board, mv);
+    let attacker = match board.piece_on(mv.from) {
+        Some(Piece::King) | None => 0,
+        Some(piece) => piece_value(piece),
+    };
+    let promotion = mv.promotion.map_or(0, piece_value);
+    (victim + promotion) * 10 - attacker / 10
+}
+
+// Removes and returns the highest scoring move. A full sort would be wasted
+// work, as a cutoff usually comes before most moves are looked at.
+fn pick_best(moves: &mut Vec<(Move, i32)>) -> Option<Move> {
+    let best = moves
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, (_, score))| *score)
+        .map(|(i, _)| i)?;
+    Some(moves.swap_remove(best).0)
+}
+
+#[cfg(test)]
+mod tests {
+    use cozy_chess::*;
+    use crate::movepick::*;
+
+    fn picked(board: &Board, tt_move: Option<Move>, killers: [Option<Move>; 2]) -> Vec<Move> {
+        let history = History::default();
+        let mut picker = MovePicker::new(tt_move, killers);
+        let mut moves = Vec::new();
+        while let Some(mv) = picker.next(board, &history) {
+            moves.push(mv);
+        }
+        moves
+    }
+
+    #[test]
+    fn test_stage_order() {
+        // White can take the queen with a pawn or with the queen, or take a
+        // pawn with the queen and lose the queen to the recapture.
+        let board = Board::from_fen("4k3/8/8/2q1p3/1P1Q4/2N5/8/4K3 w - - 0 1", false).unwrap();
+        let tt_move: Move = "e1f1".parse().unwrap();
+        let killer: Move = "d4d5".parse().unwrap();
+        let moves = picked(&board, Some(tt_move), [Some(killer), None]);
+
+        let mut count = 0;
+        board.generate_moves(|moves| {
+            count += moves.len();
+            false
+        });
+        assert_eq!(moves.len(), count, "every legal move exactly once");
+        assert_eq!(moves[0], tt_move);
+        assert_eq!(moves[1], "b4c5".parse().unwrap());
+        assert_eq!(moves[2], "d4c5".parse().unwrap());
+        assert_eq!(moves[3], killer);
+        assert_eq!(moves[moves.len() - 1], "d4e5".parse().unwrap());
+    }
+
+    #[test]
+    fn test_captures_only() {
+        // En passant and the queen promotion, but not the underpromotions.
+        let board = Board::from_fen("4k3/1P6/8/3pP3/8/8/8/4K3 w - d6 0 1", false).unwrap();
+        let mut picker = MovePicker::new_captures();
+        let mut moves = Vec::new();
+        while let Some(mv) = picker.next(&board, &History::default()) {
+            moves.push(mv);
+        }
+        moves.sort_by_key(|mv| mv.to);
+        assert_eq!(moves, ["e5d6".parse().unwrap(), "b7b8q".parse().unwrap()]);
+    }
+
+    #[test]
+    fn test_illegal_tt_move_and_killers_are_skipped() {
+        let board = Board::default();
+        let bogus: Move = "e2e5".parse().unwrap();
+        let moves = picked(&board, Some(bogus), [Some(bogus), None]);
+        assert_eq!(moves.len(), 20);
+        assert!(!moves.contains(&bogus));
+    }
+}
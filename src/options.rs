@@ -0,0 +1,202 @@
+// This file declares the options gleam exposes to the GUI over UCI, and holds
+// the values the GUI has chosen for them with setoption.
+
+pub enum OptionKind {
+    Spin { default: i64, min: i64, max: i64 },
+    Check { default: bool },
+    Button,
+}
+
+pub struct UciOption {
+    pub name: &'static str,
+    pub kind: OptionKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OptionValue {
+    Spin(i64),
+    Check(bool),
+    Button,
+}
+
+pub const HASH: UciOption = UciOption {
+    name: "Hash",
+    kind: OptionKind::Spin { default: 8, min: 1, max: 4096 },
+};
+
+pub const CLEAR_HASH: UciOption = UciOption {
+    name: "Clear Hash",
+    kind: OptionKind::Button,
+};
+
+pub const MOVE_OVERHEAD: UciOption = UciOption {
+    name: "Move Overhead",
+    kind: OptionKind::Spin { default: 30, min: 0, max: 5000 },
+};
+
+// Announcing Ponder tells the GUI that gleam can think on the opponent's time.
+pub const PONDER: UciOption = UciOption {
+    name: "Ponder",
+    kind: OptionKind::Check { default: false },
+};
+
+// How many of the best root moves are searched and reported with their own
+// lines, for analysis.
+pub const MULTI_PV: UciOption = UciOption {
+    name: "MultiPV",
+    kind: OptionKind::Spin { default: 1, min: 1, max: 64 },
+};
+
+// How much worse than even gleam rates a draw, in centipawns. Positive values
+// make it avoid draws against weaker opponents; negative values make it seek
+// them against stronger ones.
+pub const CONTEMPT: UciOption = UciOption {
+    name: "Contempt",
+    kind: OptionKind::Spin { default: 0, min: -500, max: 500 },
+};
+
+// Number of threads searching in parallel, sharing one transposition table.
+pub const THREADS: UciOption = UciOption {
+    name: "Threads",
+    kind: OptionKind::Spin { default: 1, min: 1, max: 256 },
+};
+
+// Every option, in the order it is announced to the GUI.
+pub const OPTIONS: &[UciOption] = &[HASH, CLEAR_HASH, THREADS, MOVE_OVERHEAD, PONDER, MULTI_PV, CONTEMPT];
+
+impl UciOption {
+    // The line announcing this option in reply to the uci command.
+    pub fn declaration(&self) -> String {
+        match self.kind {
+            OptionKind::Spin { default, min, max } => {
+                format!("option name {} type spin default {} min {} max {}", self.name, default, min, max)
+            }
+            OptionKind::Check { default } => {
+                format!("option name {} type check default {}", self.name, default)
+            }
+            OptionKind::Button => format!("option name {} type button", self.name),
+        }
+    }
+
+    // Checks a value sent by the GUI against this option's type and range.
+    // Arguments: The text after "value", if there was any
+    // Returns: The parsed value, or a message saying why it was rejected
+    pub fn parse_value(&self, value: Option<&str>) -> Result<OptionValue, String> {
+        match self.kind {
+            OptionKind::Spin { min, max, .. } => {
+                let value = value.ok_or_else(|| format!("{} needs a value", self.name))?;
+                let value: i64 = value
+                    .parse()
+                    .map_err(|_| format!("{} expects a number, got '{}'", self.name, value))?;
+                if value < min || value > max {
+                    return Err(format!("{} must be between {} and {}", self.name, min, max));
+                }
+                Ok(OptionValue::Spin(value))
+            }
+            OptionKind::Check { .. } => match value {
+                Some("true") => Ok(OptionValue::Check(true)),
+                Some("false") => Ok(OptionValue::Check(false)),
+                _ => Err(format!("{} expects true or false", self.name)),
+            },
+            OptionKind::Button => Ok(OptionValue::Button),
+        }
+    }
+
+    fn spin_default(&self) -> i64 {
+        match self.kind {
+            OptionKind::Spin { default, .. } => default,
+            _ => unreachable!("{} is not a spin option", self.name),
+        }
+    }
+
+    fn check_default(&self) -> bool {
+        match self.kind {
+            OptionKind::Check { default } => default,
+            _ => unreachable!("{} is not a check option", self.name),
+        }
+    }
+}
+
+// Looks up an option by name. GUIs are not consistent about case, so the
+// comparison ignores it.
+pub fn find_option(name: &str) -> Option<&'static UciOption> {
+    OPTIONS.iter().find(|option| option.name.eq_ignore_ascii_case(name))
+}
+
+// Splits "setoption name <name> value <value>" into its name and value. Both
+// may contain spaces.
+// Arguments: The tokens after setoption
+// Returns: The name and the value, if the command named an option
+pub fn parse_setoption(tokens: std::str::SplitWhitespace<'_>) -> Option<(String, Option<String>)> {
+    let mut name: Vec<&str> = Vec::new();
+    let mut value: Option<Vec<&str>> = None;
+    let mut in_name = false;
+    for token in tokens {
+        match token {
+            "name" if value.is_none() && name.is_empty() => in_name = true,
+            "value" if in_name => {
+                in_name = false;
+                value = Some(Vec::new());
+            }
+            _ if in_name => name.push(token),
+            _ => {
+                if let Some(value) = value.as_mut() {
+                    value.push(token);
+                }
+            }
+        }
+    }
+    if name.is_empty() {
+        return None;
+    }
+    Some((name.join(" "), value.map(|value| value.join(" "))))
+}
+
+/// Option values the search needs to know about.
+#[derive(Clone, Debug)]
+pub struct EngineOptions {
+    pub hash_mib: usize,
+    pub threads: usize,
+    pub move_overhead: u64,
+    pub ponder: bool,
+    pub multipv: usize,
+    pub contempt: i32,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        EngineOptions {
+            hash_mib: HASH.spin_default() as usize,
+            threads: THREADS.spin_default() as usize,
+            move_overhead: MOVE_OVERHEAD.spin_default() as u64,
+            ponder: PONDER.check_default(),
+            multipv: MULTI_PV.spin_default() as usize,
+            contempt: CONTEMPT.spin_default() as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::*;
+
+    #[test]
+    fn test_parse_setoption() {
+        let parsed = parse_setoption("name Clear Hash".split_whitespace());
+        assert_eq!(parsed, Some(("Clear Hash".to_string(), None)));
+
+        let parsed = parse_setoption("name Move Overhead value 100".split_whitespace());
+        assert_eq!(parsed, Some(("Move Overhead".to_string(), Some("100".to_string()))));
+
+        assert_eq!(parse_setoption("".split_whitespace()), None);
+    }
+
+    #[test]
+    fn test_parse_value() {
+        let hash = find_option("hash").unwrap();
+        assert_eq!(hash.parse_value(Some("64")), Ok(OptionValue::Spin(64)));
+        assert!(hash.parse_value(Some("0")).is_err());
+        assert!(hash.parse_value(Some("lots")).is_err());
+        assert!(hash.parse_value(None).is_err());
+    }
+}
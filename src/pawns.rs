@@ -0,0 +1,256 @@
+// This file evaluates the pawn structure: doubled, isolated, backward,
+// connected and passed pawns. Pawns move rarely and never backwards, so the
+// same structure comes up again and again during a search. Everything that
+// depends on the pawns alone is cached in a table keyed by a Zobrist hash of
+// the pawns; only the passed pawn terms that depend on the other pieces are
+// worked out every time.
+
+use crate::eval::*;
+use cozy_chess::*;
+
+// Penalties for each pawn beyond the first on a file, for a pawn with no
+// friendly pawns on the files beside it, and for a pawn that has fallen
+// behind its neighbours and cannot safely advance. (middlegame, endgame)
+const DOUBLED: (EvalInt, EvalInt) = (-10, -25);
+const ISOLATED: (EvalInt, EvalInt) = (-12, -15);
+const BACKWARD: (EvalInt, EvalInt) = (-8, -12);
+
+// Bonuses by relative rank for a pawn defended by a pawn or standing beside
+// one, and for a passed pawn.
+const CONNECTED: [EvalInt; 8] = [0, 5, 8, 12, 20, 35, 60, 0];
+const PASSED_MG: [EvalInt; 8] = [0, 5, 10, 15, 30, 50, 80, 0];
+const PASSED_EG: [EvalInt; 8] = [0, 10, 15, 25, 50, 90, 140, 0];
+
+// A passed pawn with anything standing on its way keeps only
+// 1 / PASSED_BLOCKED_DIVISOR of its bonus.
+const PASSED_BLOCKED_DIVISOR: EvalInt = 2;
+
+// Endgame bonus for a passed pawn the enemy king cannot catch, when the
+// enemy has only pawns left to stop it with.
+const UNSTOPPABLE: EvalInt = 500;
+
+// Entries in each thread's pawn hash table. A power of two.
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+// Zobrist keys for a pawn of each colour on each square. They are fixed at
+// compile time with splitmix64, so no random number generator is needed.
+const PAWN_KEYS: [[u64; Square::NUM]; Color::NUM] = {
+    let mut keys = [[0; Square::NUM]; Color::NUM];
+    let mut state: u64 = 0x2545F4914F6CDD1D;
+    let mut i = 0;
+    while i < Color::NUM * Square::NUM {
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        keys[i / Square::NUM][i % Square::NUM] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
+};
+
+// The key of one pawn. A position's pawn key is these XORed together for
+// every pawn on the board.
+pub fn pawn_key(color: Color, square: Square) -> u64 {
+    PAWN_KEYS[color as usize][square as usize]
+}
+
+// Every square on a rank strictly ahead of rank, as seen by color.
+pub fn forward_ranks(color: Color, rank: Rank) -> BitBoard {
+    let rank = rank as u32;
+    match color {
+        Color::White if rank == 7 => BitBoard::EMPTY,
+        Color::White => BitBoard(u64::MAX << (8 * (rank + 1))),
+        Color::Black => BitBoard((1u64 << (8 * rank)) - 1),
+    }
+}
+
+// The squares a pawn still has to cross to promote.
+pub fn front_span(color: Color, square: Square) -> BitBoard {
+    forward_ranks(color, square.rank()) & square.file().bitboard()
+}
+
+// How many king moves it takes to get from one square to the other.
+pub fn distance(a: Square, b: Square) -> i32 {
+    let files = (a.file() as i32 - b.file() as i32).abs();
+    let ranks = (a.rank() as i32 - b.rank() as i32).abs();
+    files.max(ranks)
+}
+
+#[derive(Clone, Copy, Default)]
+struct PawnEntry {
+    key: u64,
+    // Score of the structure from White's side.
+    mg: EvalInt,
+    eg: EvalInt,
+    // Passed pawns of both colours.
+    passed: BitBoard,
+}
+
+/// Caches the evaluation of pawn structures by pawn key. Each search thread
+/// has its own.
+pub struct PawnTable {
+    entries: Vec<PawnEntry>,
+}
+
+impl Default for PawnTable {
+    fn default() -> Self {
+        PawnTable {
+            entries: vec![PawnEntry::default(); PAWN_TABLE_SIZE],
+        }
+    }
+}
+
+impl PawnTable {
+    // Finds the entry for the board's pawns, working it out on a miss.
+    fn probe(&mut self, board: &Board, key: u64) -> PawnEntry {
+        let slot = &mut self.entries[key as usize & (PAWN_TABLE_SIZE - 1)];
+        // A board without pawns has key 0, which matches an empty entry, and
+        // an empty entry is indeed its correct score.
+        if slot.key != key {
+            *slot = evaluate_structure(board, key);
+        }
+        *slot
+    }
+}
+
+// Scores the pawns of both sides from scratch.
+fn evaluate_structure(board: &Board, key: u64) -> PawnEntry {
+    let mut entry = PawnEntry {
+        key,
+        ..PawnEntry::default()
+    };
+    for color in Color::ALL {
+        let sign = if color == Color::White { 1 } else { -1 };
+        let ours = board.colored_pieces(color, Piece::Pawn);
+        let theirs = board.colored_pieces(!color, Piece::Pawn);
+        let mut mg = 0;
+        let mut eg = 0;
+
+        for file in File::ALL {
+            let on_file = (ours & file.bitboard()).len() as EvalInt;
+            if on_file > 1 {
+                mg += DOUBLED.0 * (on_file - 1);
+                eg += DOUBLED.1 * (on_file - 1);
+            }
+        }
+
+        for square in ours {
+            let file = square.file();
+            let rank = square.rank().relative_to(color) as usize;
+            let neighbours = ours & file.adjacent();
+            let ahead = forward_ranks(color, square.rank());
+
+            // Friendly pawns that defend this one, or stand beside it.
+            let supporters = get_pawn_attacks(square, !color) & ours;
+            let phalanx = neighbours & square.rank().bitboard();
+            if !(supporters | phalanx).is_empty() {
+                mg += CONNECTED[rank];
+                eg += CONNECTED[rank];
+            }
+
+            if neighbours.is_empty() {
+                mg += ISOLATED.0;
+                eg += ISOLATED.1;
+            } else if (neighbours & !ahead).is_empty() {
+                // Every neighbour has already gone past, so none can ever
+                // defend this pawn. It is backward if advancing it would run
+                // into an enemy pawn's attack.
+                let stop = front_span(color, square).next_square();
+                if stop.is_some_and(|stop| !(get_pawn_attacks(stop, color) & theirs).is_empty()) {
+                    mg += BACKWARD.0;
+                    eg += BACKWARD.1;
+                }
+            }
+
+            let blockers = theirs & ahead & (file.bitboard() | file.adjacent());
+            if blockers.is_empty() {
+                entry.passed |= square.bitboard();
+            }
+        }
+
+        entry.mg += sign * mg;
+        entry.eg += sign * eg;
+    }
+    entry
+}
+
+// Scores the passed pawns, which also depends on the other pieces. A pawn
+// with its path blocked is worth less. In a pawn ending a pawn whose
+// promotion square the enemy king is too far away to reach (the king is not
+// in the pawn's "square") will queen, so it is worth far more.
+fn evaluate_passed(board: &Board, passed: BitBoard) -> (EvalInt, EvalInt) {
+    let mut mg = 0;
+    let mut eg = 0;
+    for color in Color::ALL {
+        let sign = if color == Color::White { 1 } else { -1 };
+        let enemy_king = board.king(!color);
+        let enemy_has_pieces = !(board.colors(!color) & !board.pieces(Piece::Pawn) & !board.pieces(Piece::King)).is_empty();
+        for square in passed & board.colors(color) {
+            let rank = square.rank().relative_to(color) as usize;
+            let path = front_span(color, square);
+            let (mut bonus_mg, mut bonus_eg) = (PASSED_MG[rank], PASSED_EG[rank]);
+            if !(path & board.occupied()).is_empty() {
+                bonus_mg /= PASSED_BLOCKED_DIVISOR;
+                bonus_eg /= PASSED_BLOCKED_DIVISOR;
+            } else if !enemy_has_pieces {
+                let promotion = Square::new(square.file(), Rank::Eighth.relative_to(color));
+                // From its starting rank a pawn can move two squares at once.
+                let pawn_moves = (7 - rank as i32).min(5);
+                let tempo = (board.side_to_move() == !color) as i32;
+                if pawn_moves < distance(enemy_king, promotion) - tempo {
+                    bonus_eg += UNSTOPPABLE;
+                }
+            }
+            mg += sign * bonus_mg;
+            eg += sign * bonus_eg;
+        }
+    }
+    (mg, eg)
+}
+
+// Evaluates the pawns of a board.
+// Arguments: The board, its pawn key and the thread's pawn table
+// Returns: The middlegame and endgame scores from White's side
+pub fn evaluate_pawns(board: &Board, key: u64, table: &mut PawnTable) -> (EvalInt, EvalInt) {
+    let entry = table.probe(board, key);
+    let (passed_mg, passed_eg) = evaluate_passed(board, entry.passed);
+    (entry.mg + passed_mg, entry.eg + passed_eg)
+}
+
+#[cfg(test)]
+mod tests {
+    use cozy_chess::*;
+    use crate::pawns::*;
+
+    fn structure(fen: &str) -> PawnEntry {
+        let board = Board::from_fen(fen, false).unwrap();
+        evaluate_structure(&board, 1)
+    }
+
+    #[test]
+    fn test_structure() {
+        // White's doubled, isolated c-pawns against Black's healthy pawns.
+        let entry = structure("4k3/1pp5/8/8/8/2P5/2P5/4K3 w - - 0 1");
+        assert_eq!(entry.mg, DOUBLED.0 + 2 * ISOLATED.0 - CONNECTED[1] - CONNECTED[1]);
+        assert!(entry.passed.is_empty());
+
+        // The a-pawn is passed; the e-pawn is not, as the d-pawn guards its path.
+        let entry = structure("4k3/3p4/8/P7/4P3/8/8/4K3 w - - 0 1");
+        assert_eq!(entry.passed, Square::A5.bitboard());
+
+        // The d-pawn lags behind the e-pawn and cannot advance past the
+        // c-pawn's attack. Black's c-pawn is isolated.
+        let entry = structure("4k3/8/8/8/2p1P3/8/3P4/4K3 w - - 0 1");
+        assert_eq!(entry.eg, BACKWARD.1 - ISOLATED.1);
+    }
+
+    #[test]
+    fn test_rule_of_the_square() {
+        let inside = Board::from_fen("8/8/8/2k5/P7/8/8/K7 w - - 0 1", false).unwrap();
+        let outside = Board::from_fen("8/8/8/8/P7/8/7k/K7 w - - 0 1", false).unwrap();
+        let passed = Square::A4.bitboard();
+        assert_eq!(evaluate_passed(&inside, passed).1, PASSED_EG[3]);
+        assert_eq!(evaluate_passed(&outside, passed).1, PASSED_EG[3] + UNSTOPPABLE);
+    }
+}
@@ -0,0 +1,141 @@
+// This file evaluates how active the pieces are: how many squares each
+// knight, bishop, rook and queen can safely move to, the bishop pair, rooks
+// on open files and on the seventh rank, and knights and bishops on outposts
+// that enemy pawns can never chase away.
+
+use crate::eval::*;
+use crate::pawns::forward_ranks;
+use cozy_chess::*;
+
+// Mobility scores each move above or below a typical count for the piece,
+// indexed by Piece. (middlegame, endgame)
+const MOBILITY_BASE: [EvalInt; Piece::NUM] = [0, 4, 6, 7, 13, 0];
+const MOBILITY_MG: [EvalInt; Piece::NUM] = [0, 4, 5, 2, 1, 0];
+const MOBILITY_EG: [EvalInt; Piece::NUM] = [0, 4, 5, 4, 2, 0];
+
+const BISHOP_PAIR: (EvalInt, EvalInt) = (30, 50);
+
+// Rooks on files without pawns of their own side, and also without enemy
+// pawns, and rooks on the seventh rank while the enemy king is stuck on the
+// eighth or enemy pawns are still on the seventh.
+const ROOK_SEMI_OPEN: (EvalInt, EvalInt) = (12, 5);
+const ROOK_OPEN: (EvalInt, EvalInt) = (25, 10);
+const ROOK_SEVENTH: (EvalInt, EvalInt) = (20, 30);
+
+// Knights and bishops on the enemy half, defended by a pawn and beyond the
+// reach of enemy pawns.
+const KNIGHT_OUTPOST: (EvalInt, EvalInt) = (25, 15);
+const BISHOP_OUTPOST: (EvalInt, EvalInt) = (15, 8);
+
+// The squares a piece on square attacks, with sliders stopped by occupied.
+pub fn attacks(piece: Piece, square: Square, color: Color, occupied: BitBoard) -> BitBoard {
+    match piece {
+        Piece::Pawn => get_pawn_attacks(square, color),
+        Piece::Knight => get_knight_moves(square),
+        Piece::Bishop => get_bishop_moves(square, occupied),
+        Piece::Rook => get_rook_moves(square, occupied),
+        Piece::Queen => get_bishop_moves(square, occupied) | get_rook_moves(square, occupied),
+        Piece::King => get_king_moves(square),
+    }
+}
+
+// Every square color's pawns attack.
+fn pawn_attacks(board: &Board, color: Color) -> BitBoard {
+    board
+        .colored_pieces(color, Piece::Pawn)
+        .iter()
+        .fold(BitBoard::EMPTY, |attacked, square| attacked | get_pawn_attacks(square, color))
+}
+
+// Scores color's pieces, from color's side.
+fn evaluate_side(board: &Board, color: Color) -> (EvalInt, EvalInt) {
+    let occupied = board.occupied();
+    let ours = board.colors(color);
+    let our_pawns = board.colored_pieces(color, Piece::Pawn);
+    let their_pawns = board.colored_pieces(!color, Piece::Pawn);
+    let our_pawn_attacks = pawn_attacks(board, color);
+    // Squares attacked by enemy pawns are not counted as mobility, as a
+    // piece moving there would simply be taken.
+    let safe = !ours & !pawn_attacks(board, !color);
+    let mut mg = 0;
+    let mut eg = 0;
+
+    for piece in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen] {
+        for square in board.colored_pieces(color, piece) {
+            let moves = (attacks(piece, square, color, occupied) & safe).len() as EvalInt;
+            let above_base = moves - MOBILITY_BASE[piece as usize];
+            mg += above_base * MOBILITY_MG[piece as usize];
+            eg += above_base * MOBILITY_EG[piece as usize];
+        }
+    }
+
+    if board.colored_pieces(color, Piece::Bishop).len() >= 2 {
+        mg += BISHOP_PAIR.0;
+        eg += BISHOP_PAIR.1;
+    }
+
+    let seventh = Rank::Seventh.relative_to(color);
+    let enemy_king_on_eighth = board.king(!color).rank() == Rank::Eighth.relative_to(color);
+    for square in board.colored_pieces(color, Piece::Rook) {
+        let file = square.file().bitboard();
+        if (our_pawns & file).is_empty() {
+            let (bonus_mg, bonus_eg) = if (their_pawns & file).is_empty() { ROOK_OPEN } else { ROOK_SEMI_OPEN };
+            mg += bonus_mg;
+            eg += bonus_eg;
+        }
+        if square.rank() == seventh && (enemy_king_on_eighth || !(their_pawns & seventh.bitboard()).is_empty()) {
+            mg += ROOK_SEVENTH.0;
+            eg += ROOK_SEVENTH.1;
+        }
+    }
+
+    for (piece, (bonus_mg, bonus_eg)) in [(Piece::Knight, KNIGHT_OUTPOST), (Piece::Bishop, BISHOP_OUTPOST)] {
+        for square in board.colored_pieces(color, piece) {
+            let rank = square.rank().relative_to(color) as usize;
+            let chasers = their_pawns & square.file().adjacent() & forward_ranks(color, square.rank());
+            if (3..=5).contains(&rank) && our_pawn_attacks.has(square) && chasers.is_empty() {
+                mg += bonus_mg;
+                eg += bonus_eg;
+            }
+        }
+    }
+
+    (mg, eg)
+}
+
+// Evaluates the activity of both sides' pieces.
+// Returns: The middlegame and endgame scores from White's side
+pub fn evaluate_pieces(board: &Board) -> (EvalInt, EvalInt) {
+    let (white_mg, white_eg) = evaluate_side(board, Color::White);
+    let (black_mg, black_eg) = evaluate_side(board, Color::Black);
+    (white_mg - black_mg, white_eg - black_eg)
+}
+
+#[cfg(test)]
+mod tests {
+    use cozy_chess::*;
+    use crate::pieces::*;
+
+    #[test]
+    fn test_mobility_ignores_pawn_attacked_squares() {
+        // The knight on a1 reaches b3 and c2; both are attacked by Black's
+        // pawns in the second position.
+        let free = Board::from_fen("4k3/8/8/8/8/8/8/N3K3 w - - 0 1", false).unwrap();
+        let covered = Board::from_fen("4k3/8/8/8/p7/3p4/8/N3K3 w - - 0 1", false).unwrap();
+        let knight = MOBILITY_MG[Piece::Knight as usize];
+        let (free_mg, _) = evaluate_side(&free, Color::White);
+        let (covered_mg, _) = evaluate_side(&covered, Color::White);
+        assert_eq!(free_mg - covered_mg, 2 * knight);
+    }
+
+    #[test]
+    fn test_outpost() {
+        // A knight on d5 defended by the e4 pawn, with no black c- or
+        // e-pawns left to drive it away, against the same knight on d4.
+        let outpost = Board::from_fen("4k3/8/8/3N4/4P3/8/8/4K3 w - - 0 1", false).unwrap();
+        let home = Board::from_fen("4k3/8/8/8/3NP3/8/8/4K3 w - - 0 1", false).unwrap();
+        let (outpost_mg, _) = evaluate_side(&outpost, Color::White);
+        let (home_mg, _) = evaluate_side(&home, Color::White);
+        assert_eq!(outpost_mg - home_mg, KNIGHT_OUTPOST.0);
+    }
+}
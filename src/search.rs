@@ -1,259 +1,1214 @@
 use crate::eval::*;
+use crate::kpk;
+use crate::movepick::*;
+use crate::options::EngineOptions;
+use crate::pawns::PawnTable;
+use crate::see::*;
+use crate::tt::*;
 use cozy_chess::*;
-use std::cmp::max;
-use std::collections::HashMap;
+use cozy_chess::util::display_uci_move;
+use std::cmp::{Reverse, max};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
-const EVAL_WORST: EvalInt = -(EvalInt::MAX);
-const EVAL_BEST: EvalInt = EvalInt::MAX;
+// Deepest iteration the iterative deepening loop will start.
+pub const MAX_DEPTH: usize = 64;
 
-const magicConstant : usize = (1 << 20) / 12; 
+// How many nodes are searched between checks of the clock.
+const CHECK_INTERVAL: u64 = 1024;
 
+// Near the leaves, captures losing more than SEE_PRUNE_MARGIN per ply of
+// remaining depth by static exchange are not searched.
+const SEE_PRUNE_DEPTH: usize = 3;
+const SEE_PRUNE_MARGIN: EvalInt = 100;
 
-#[derive(Clone, Copy, Debug)]
-pub struct TranspositionEntry {
-    pub best_move: Move,
-    pub eval: EvalInt,
-    pub depth: u8,
+// Quiescence search skips captures that would leave the eval more than
+// DELTA_MARGIN short of alpha even after winning the captured piece.
+const DELTA_MARGIN: EvalInt = 200;
+
+// From ASPIRATION_DEPTH on, the root is searched with a window of
+// +-ASPIRATION_DELTA around the previous iteration's eval.
+const ASPIRATION_DEPTH: usize = 4;
+const ASPIRATION_DELTA: EvalInt = 25;
+
+// Null-move pruning is tried from NULL_MOVE_DEPTH on. The null move is
+// searched NULL_MOVE_REDUCTION plies shallower than the moves would be, and
+// one ply shallower still for every NULL_MOVE_DIVISOR plies of depth.
+const NULL_MOVE_DEPTH: usize = 3;
+const NULL_MOVE_REDUCTION: usize = 3;
+const NULL_MOVE_DIVISOR: usize = 6;
+
+// From NULL_VERIFY_DEPTH on, a null-move cutoff is only trusted once a reduced
+// search without null moves confirms it, which guards against zugzwang.
+const NULL_VERIFY_DEPTH: usize = 10;
+
+// Late move reductions apply from LMR_DEPTH on, to quiet moves searched after
+// the first LMR_MIN_MOVES. History scores move the reduction by a ply per
+// LMR_HISTORY_DIVISOR.
+const LMR_DEPTH: usize = 3;
+const LMR_MIN_MOVES: usize = 3;
+const LMR_HISTORY_DIVISOR: i32 = 4096;
+
+// Up to LMP_DEPTH, quiet moves beyond LMP_BASE + depth * depth are not
+// searched at all.
+const LMP_DEPTH: usize = 3;
+const LMP_BASE: usize = 3;
+
+// Up to FUTILITY_DEPTH, quiet moves are not searched when the static eval
+// trails alpha by more than FUTILITY_MARGIN per ply of remaining depth.
+const FUTILITY_DEPTH: usize = 3;
+const FUTILITY_MARGIN: EvalInt = 120;
+
+// Singular extensions are tried from SINGULAR_DEPTH on, for a transposition
+// table move whose entry is at most SINGULAR_TT_DEPTH plies shallower than
+// the node. The move is singular if every other move fails low against its
+// eval minus SINGULAR_MARGIN per ply of depth.
+const SINGULAR_DEPTH: usize = 8;
+const SINGULAR_TT_DEPTH: usize = 3;
+const SINGULAR_MARGIN: EvalInt = 2;
+
+// Deepest ply any line of the search may reach, including quiescence.
+pub const MAX_PLY: usize = 128;
+
+// Score for delivering checkmate at the root. Mates further away score
+// MATE - ply so that shorter mates are preferred.
+pub const MATE: EvalInt = 30000;
+
+// Any score beyond this bound is a forced mate.
+pub const MATE_BOUND: EvalInt = MATE - MAX_PLY as EvalInt;
+
+// Bounds of the full search window, just outside any real score. They are
+// kept far from EvalInt's limits so that negating them or widening windows
+// around them cannot overflow.
+const EVAL_WORST: EvalInt = -(MATE + 1);
+const EVAL_BEST: EvalInt = MATE + 1;
+
+
+/// Limits given to a search by the UCI `go` command. Times are in milliseconds.
+#[derive(Clone, Debug, Default)]
+pub struct SearchLimits {
+    pub wtime: Option<u64>,
+    pub btime: Option<u64>,
+    pub winc: Option<u64>,
+    pub binc: Option<u64>,
+    pub movestogo: Option<u64>,
+    pub movetime: Option<u64>,
+    pub depth: Option<usize>,
+    pub nodes: Option<u64>,
+    pub infinite: bool,
+    pub ponder: bool,
+}
+
+impl SearchLimits {
+    // Works out how long the side to move may think, keeping move_overhead
+    // milliseconds in reserve for GUI and network lag.
+    // Returns: A soft limit after which no new iteration is started, and a
+    // hard limit at which the current iteration is abandoned. Both are None
+    // when the search is not bounded by time.
+    fn time_budget(&self, side: Color, move_overhead: u64) -> (Option<Duration>, Option<Duration>) {
+        if self.infinite {
+            return (None, None);
+        }
+        if let Some(movetime) = self.movetime {
+            let time = Duration::from_millis(movetime.saturating_sub(move_overhead).max(1));
+            return (Some(time), Some(time));
+        }
+
+        let (time, inc) = match side {
+            Color::White => (self.wtime, self.winc),
+            Color::Black => (self.btime, self.binc),
+        };
+        let Some(time) = time else {
+            return (None, None);
+        };
+        let inc = inc.unwrap_or(0);
+        let time = time.saturating_sub(move_overhead).max(1);
+        let moves_left = self.movestogo.unwrap_or(30).clamp(1, 50);
+
+        let hard = (time / 2).max(1);
+        let soft = (time / moves_left + inc * 3 / 4).min(hard);
+        let hard = (soft * 3).min(hard);
+
+        (Some(Duration::from_millis(soft)), Some(Duration::from_millis(hard)))
+    }
+}
+
+// Formats a score for an info line, as "cp <n>" or "mate <moves>".
+fn uci_score(eval: EvalInt) -> String {
+    if eval >= MATE_BOUND {
+        format!("mate {}", (MATE - eval + 1) / 2)
+    } else if eval <= -MATE_BOUND {
+        format!("mate {}", -(MATE + eval + 1) / 2)
+    } else {
+        format!("cp {}", eval)
+    }
 }
 
-pub struct HashTable {
-    data: Vec<Option<(u64, TranspositionEntry)>>,
-    size: usize,
+/// Flags the UCI thread uses to control a search running on another thread.
+#[derive(Default)]
+pub struct SearchSignals {
+    pub stop: AtomicBool,
+    // Set while searching on the opponent's time. Cleared by ponderhit,
+    // which starts our clock.
+    pub ponder: AtomicBool,
 }
 
-pub fn mib_to_n<T: Sized>(mib: usize) -> usize {
-    let bytes = mib * (1 << 20);
-    let entry_size = std::mem::size_of::<(u64, Option<T>)>();
+// One of the MultiPV lines found by an iteration.
+struct RootLine {
+    eval: EvalInt,
+    pv: Vec<Move>,
+}
 
-    bytes / entry_size
+// State the threads of one search share with each other.
+#[derive(Default)]
+struct SharedSearch {
+    // Nodes searched by every thread, topped up every CHECK_INTERVAL nodes.
+    nodes: AtomicU64,
+    // Raised by the main thread once it has its move, to stop the helpers.
+    done: AtomicBool,
+    // Hashes of the positions played before the root, oldest first.
+    game_history: Vec<u64>,
+    // How much worse than even a draw is for the side to move at the root.
+    contempt: EvalInt,
 }
 
-impl Default for HashTable {
-    fn default() -> Self {
-        Self::new(8)
+// Builds the late move reduction table. Reductions grow with the log of the
+// depth and of the move number, so late moves at high depth are reduced most.
+fn reduction_table() -> Box<[[usize; 64]; 64]> {
+    let mut table = Box::new([[0; 64]; 64]);
+    for (depth, row) in table.iter_mut().enumerate().skip(1) {
+        for (moves, reduction) in row.iter_mut().enumerate().skip(1) {
+            *reduction = (0.75 + (depth as f64).ln() * (moves as f64).ln() / 2.25) as usize;
+        }
     }
+    table
+}
+
+// What the search knows about the node at one ply of the current line.
+#[derive(Clone, Copy, Default)]
+struct StackEntry {
+    // Set when the node was reached by passing.
+    null_move: bool,
+    // A move left out of the node's search, while checking whether it is
+    // singular.
+    excluded: Option<Move>,
+    // Extensions made on the line from the root to the node.
+    extensions: usize,
+    // The node's position, for spotting repetitions.
+    hash: u64,
+    // Material and piece-square sums of the node's position.
+    eval_state: EvalState,
+}
+
+// Counts the legal moves of a position.
+fn legal_move_count(board: &Board) -> usize {
+    let mut count = 0;
+    board.generate_moves(|moves| {
+        count += moves.len();
+        false
+    });
+    count
 }
 
-impl HashTable {
-    pub fn new(size_mib: usize) -> Self {
-        let size = mib_to_n::<TranspositionEntry>(size_mib);
-        Self::new_n(size)
+// State shared by every node of a single search thread.
+struct Searcher<'a> {
+    transposition_table: &'a HashTable,
+    signals: &'a SearchSignals,
+    shared: &'a SharedSearch,
+    nodes: u64,
+    // How many of this thread's nodes have been added to shared.nodes.
+    flushed: u64,
+    seldepth: usize,
+    start: Instant,
+    // When our clock started: the start of the search, or the ponderhit if
+    // we were pondering.
+    clock: Instant,
+    pondering: bool,
+    hard_limit: Option<Duration>,
+    node_limit: Option<u64>,
+    stopped: bool,
+    // Triangular principal variation table: pv[ply] holds the best line
+    // found so far from the node at that ply.
+    pv: Vec<Vec<Move>>,
+    // Two quiet moves per ply that recently caused a beta cutoff there.
+    killers: Vec<[Option<Move>; 2]>,
+    history: History,
+    pawn_table: PawnTable,
+    // reductions[depth][moves] is how many plies late move reductions take
+    // off the moves-th move searched at depth.
+    reductions: Box<[[usize; 64]; 64]>,
+    // One entry per ply of the line being searched. Its length is the
+    // hard limit on how deep any line may go.
+    stack: Vec<StackEntry>,
+    // Null moves are not tried before this ply, while a null-move cutoff is
+    // being verified.
+    null_min_ply: usize,
+}
+
+impl<'a> Searcher<'a> {
+    fn new(
+        transposition_table: &'a HashTable,
+        signals: &'a SearchSignals,
+        shared: &'a SharedSearch,
+        limits: &SearchLimits,
+        start: Instant,
+        hard_limit: Option<Duration>,
+    ) -> Self {
+        Searcher {
+            transposition_table,
+            signals,
+            shared,
+            nodes: 0,
+            flushed: 0,
+            seldepth: 0,
+            start,
+            clock: start,
+            pondering: limits.ponder,
+            hard_limit,
+            node_limit: limits.nodes,
+            stopped: false,
+            pv: (0..=MAX_PLY).map(|_| Vec::with_capacity(MAX_PLY)).collect(),
+            killers: vec![[None; 2]; MAX_PLY + 1],
+            history: History::default(),
+            pawn_table: PawnTable::default(),
+            reductions: reduction_table(),
+            stack: vec![StackEntry::default(); MAX_PLY + 1],
+            null_min_ply: 0,
+        }
     }
 
-    pub fn new_n(size: usize) -> Self {
-        HashTable {
-            data: vec![None; size],
-            size,
+    // Counts a node and checks whether the search has run out of time or nodes.
+    fn tick(&mut self, ply: usize) -> bool {
+        self.nodes += 1;
+        self.seldepth = self.seldepth.max(ply + 1);
+        self.pv[ply].clear();
+        if self.stopped {
+            return true;
         }
+        if self.node_limit.is_some_and(|limit| self.total_nodes() >= limit) {
+            self.stopped = true;
+        } else if self.nodes.is_multiple_of(CHECK_INTERVAL) {
+            self.shared.nodes.fetch_add(self.nodes - self.flushed, Ordering::Relaxed);
+            self.flushed = self.nodes;
+            if self.signals.stop.load(Ordering::Relaxed)
+                || self.shared.done.load(Ordering::Relaxed)
+                || self.out_of_time(self.hard_limit)
+            {
+                self.stopped = true;
+            }
+        }
+        self.stopped
     }
 
-    pub fn trunc_hash(&self, hash: u64) -> usize {
-        hash as usize % self.size
+    // Nodes searched by all threads so far.
+    fn total_nodes(&self) -> u64 {
+        self.shared.nodes.load(Ordering::Relaxed) + self.nodes - self.flushed
     }
 
-    pub fn probe(&self, board: &Board) -> Option<TranspositionEntry> {
-        let hash = board.hash();
-        let idx = self.trunc_hash(hash);
-        if let Some((e_hash, entry)) = self.data[idx] {
-            if e_hash == hash {
-                return Some(entry);
+    // Checks our clock against a time limit. The clock does not run while
+    // pondering, and starts from zero once the GUI sends ponderhit.
+    fn out_of_time(&mut self, limit: Option<Duration>) -> bool {
+        if self.pondering {
+            if self.signals.ponder.load(Ordering::Relaxed) {
+                return false;
             }
+            self.pondering = false;
+            self.clock = Instant::now();
         }
-        None
+        limit.is_some_and(|limit| self.clock.elapsed() >= limit)
     }
 
-    pub fn set(&mut self, board: &Board, entry: TranspositionEntry) {
+    // Checks whether the position at ply has been seen before, looking back
+    // no further than the last capture or pawn move, which cannot be undone.
+    // A single repetition within the search counts as a draw, since the
+    // side that allowed it could repeat again. A repetition of the game
+    // before the root has to be a third occurrence.
+    fn is_repetition(&self, board: &Board, ply: usize) -> bool {
         let hash = board.hash();
-        let idx = self.trunc_hash(hash);
-        self.data[idx] = Some((hash, entry));
-    }
-}
-// Does quiescence search
-// was advised to implement sprt before quies
-fn quiesce(board: &mut Board, alpha: Option<EvalInt>, beta: Option<EvalInt>, transpositionTable: &mut HashTable) -> EvalInt {
-    let static_eval = board.eval();
-    let mut best_value = static_eval;
-    let mut move_list = Vec::new();
-    board.generate_moves(|moves| {
-        move_list.extend(moves);
+        let window = board.halfmove_clock() as usize;
+        let history = &self.shared.game_history;
+        let mut earlier_games = 0;
+        for back in (2..=window).step_by(2) {
+            if back <= ply {
+                // A line through a null move is not a real game, and its
+                // positions are not repetitions of each other.
+                if self.stack[ply + 2 - back].null_move || self.stack[ply + 1 - back].null_move {
+                    return false;
+                }
+                if self.stack[ply - back].hash == hash {
+                    return true;
+                }
+            } else {
+                let Some(i) = history.len().checked_sub(back - ply) else {
+                    return false;
+                };
+                if history[i] == hash {
+                    earlier_games += 1;
+                    if earlier_games == 2 {
+                        return true;
+                    }
+                }
+            }
+        }
         false
-    });
-    let mut best_mv: Option<Move> = move_list.first().copied();
+    }
 
-    let mut alpha = alpha.unwrap_or(EVAL_WORST);
-    let beta = beta.unwrap_or(EVAL_BEST);
+    // The static eval of the position at ply, from its side to move's point
+    // of view.
+    fn static_eval(&mut self, board: &Board, ply: usize) -> EvalInt {
+        evaluate(board, &self.stack[ply].eval_state, &mut self.pawn_table)
+    }
 
-    if let Some(entry) = transpositionTable.probe(&board) {
-        if entry.depth as usize >= 0 {
-            return entry.eval;
+    // The eval state after playing mv in the position at ply.
+    fn child_eval_state(&self, board: &Board, ply: usize, mv: Move) -> EvalState {
+        let mut state = self.stack[ply].eval_state;
+        state.play(board, mv);
+        state
+    }
+
+    // The score of a draw for the side to move at ply, after contempt.
+    fn draw_score(&self, ply: usize) -> EvalInt {
+        if ply.is_multiple_of(2) {
+            -self.shared.contempt
+        } else {
+            self.shared.contempt
         }
     }
 
-    if best_value >= beta {
-        return best_value;
+    // Makes mv followed by the child's line the principal variation at ply.
+    fn update_pv(&mut self, ply: usize, mv: Move) {
+        let (head, tail) = self.pv.split_at_mut(ply + 1);
+        let line = &mut head[ply];
+        line.clear();
+        line.push(mv);
+        line.extend_from_slice(&tail[0]);
     }
-    if best_value > alpha {
-        alpha = best_value;
+
+    // Remembers a quiet move that caused a beta cutoff, and marks down the
+    // quiet moves searched before it, which did not.
+    fn update_quiet_stats(&mut self, board: &Board, ply: usize, depth: usize, mv: Move, tried: &[Move]) {
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(mv) {
+            killers[1] = killers[0];
+            killers[0] = Some(mv);
+        }
+        let color = board.side_to_move();
+        self.history.update(color, mv, depth, true);
+        for &quiet in tried {
+            self.history.update(color, quiet, depth, false);
+        }
     }
 
-    let enemy_pieces = board.colors(!board.side_to_move());
-    let mut captures = Vec::new();
-    board.generate_moves(|moves| {
-        let mut captures2 = moves.clone();
-        // Bitmask to efficiently get all captures set-wise.
-        // Excluding en passant square for convenience.
-        captures2.to &= enemy_pieces;
-        captures.extend(captures2);
-        false
-    });
+    // Does quiescence search: searches only captures and queen promotions
+    // until the position is quiet, so that the static eval is never taken in
+    // the middle of an exchange. The side to move may stand pat on its eval
+    // instead of capturing, unless it is in check, when every evasion is
+    // searched.
+    fn quiesce(&mut self, board: &mut Board, ply: usize, alpha: Option<EvalInt>, beta: Option<EvalInt>) -> EvalInt {
+        if self.tick(ply) {
+            return 0;
+        }
+        if ply >= MAX_PLY - 1 {
+            return self.static_eval(board, ply);
+        }
+
+        let mut alpha = alpha.unwrap_or(EVAL_WORST);
+        let beta = beta.unwrap_or(EVAL_BEST);
+        let alpha_orig = alpha;
+
+        // Any entry is at least as deep as quiescence, but only its bound
+        // says whether its eval settles this window.
+        let tt_entry = self.transposition_table.probe(board, ply);
+        if let Some(entry) = tt_entry
+            && entry.bound.cuts_off(entry.eval, alpha, beta)
+        {
+            return entry.eval;
+        }
+
+        let in_check = !board.checkers().is_empty();
+        let static_eval = self.static_eval(board, ply);
+        let mut best_value = EVAL_WORST;
+        if !in_check {
+            best_value = static_eval;
+            if best_value >= beta {
+                return best_value;
+            }
+            alpha = alpha.max(best_value);
+        }
+
+        let mut best_mv: Option<Move> = None;
+        let mut picker = if in_check {
+            MovePicker::new(tt_entry.map(|entry| entry.best_move), self.killers[ply])
+        } else {
+            MovePicker::new_captures()
+        };
+        while let Some(mv) = picker.next(board, &self.history) {
+            // Delta pruning: a capture that could not lift the eval to alpha
+            // even with a margin to spare is not worth searching.
+            if !in_check
+                && mv.promotion.is_none()
+                && static_eval + captured_value(board, mv) + DELTA_MARGIN <= alpha
+            {
+                continue;
+            }
 
-    for mv in captures {
-        let mut new_board = board.clone();
-        new_board.play(mv);
-        let cur_score = -quiesce(&mut new_board, Some(-alpha), Some(-beta), transpositionTable);
+            let mut new_board = board.clone();
+            new_board.play(mv);
+            self.stack[ply + 1].eval_state = self.child_eval_state(board, ply, mv);
+            let cur_score = -self.quiesce(&mut new_board, ply + 1, Some(-beta), Some(-alpha));
+            if self.stopped {
+                return 0;
+            }
 
-        if cur_score >= beta {
-            return cur_score;
+            if cur_score > best_value {
+                best_value = cur_score;
+                best_mv = Some(mv);
+            }
+            if cur_score >= beta {
+                break;
+            }
+            alpha = alpha.max(cur_score);
         }
-        if cur_score > best_value {
-            best_mv = Some(mv);
-            best_value = cur_score;
+
+        // In check with no evasions is checkmate.
+        if in_check && best_mv.is_none() && best_value == EVAL_WORST {
+            return -(MATE - ply as EvalInt);
         }
-        if cur_score > alpha {
-            alpha = cur_score;
+
+        if let Some(best_mv) = best_mv {
+            self.transposition_table.set(
+                board,
+                ply,
+                TranspositionEntry {
+                    best_move: best_mv,
+                    eval: best_value,
+                    depth: 0,
+                    bound: Bound::from_window(best_value, alpha_orig, beta),
+                },
+            );
         }
-    }
-    if !best_mv.is_none() {
-        transpositionTable.set(
-            &board,
-            TranspositionEntry {
-                best_move: best_mv.unwrap(),
-                eval: best_value,
-                depth: 0,
-            },
-        );
+
+        best_value
     }
 
-    //setHash(board, best_value, transpositionTable);
+    // Search the game tree to find the best outcome for the player
+    // Uses the negamax algorithm.
+    fn minmax(&mut self, board: &mut Board, depth: usize, ply: usize, alpha: Option<EvalInt>, beta: Option<EvalInt>) -> EvalInt {
+        if depth == 0 {
+            return self.quiesce(board, ply, alpha, beta);
+        }
+        if self.tick(ply) {
+            return 0;
+        }
+        if ply >= MAX_PLY - 1 {
+            return self.static_eval(board, ply);
+        }
+        self.stack[ply].hash = board.hash();
+        if self.is_repetition(board, ply) {
+            return self.draw_score(ply);
+        }
+        // The fifty-move rule is checked before the table, whose entry may
+        // come from the same position with a lower halfmove clock. A mate on
+        // the hundredth half-move still counts.
+        if board.halfmove_clock() >= 100 && board.status() != GameStatus::Won {
+            return self.draw_score(ply);
+        }
+        // Drawn king and pawn endings need no search at all.
+        if kpk::probe_board(board) == Some(false) {
+            return self.draw_score(ply);
+        }
 
-    return best_value;
-}
+        let mut alpha = alpha.unwrap_or(EVAL_WORST);
+        let mut beta = beta.unwrap_or(EVAL_BEST);
 
-// Search the game tree to find the best outcome for the player
-// Uses the negamax algorithm.
-fn minmax(board: &mut Board, depth: usize, alpha: Option<EvalInt>, beta: Option<EvalInt>, transpositionTable: &mut HashTable) -> EvalInt {
-    if depth == 0 {
-        //return board.eval();
-        return quiesce(board, alpha, beta, transpositionTable);
-    }
+        // Mate distance pruning: even mating right here could not beat a
+        // shorter mate already found elsewhere, and being mated right here
+        // would not be worse than a quicker mate against us.
+        alpha = alpha.max(-(MATE - ply as EvalInt));
+        beta = beta.min(MATE - ply as EvalInt - 1);
+        if alpha >= beta {
+            return alpha;
+        }
+        let alpha_orig = alpha;
 
-    if let Some(entry) = transpositionTable.probe(&board) {
-        if entry.depth as usize >= depth {
+        // The table knows nothing about searches that leave a move out.
+        let excluded = self.stack[ply].excluded;
+        let tt_entry = match excluded {
+            Some(_) => None,
+            None => self.transposition_table.probe(board, ply),
+        };
+        if let Some(entry) = tt_entry
+            && entry.depth as usize >= depth
+            && entry.bound.cuts_off(entry.eval, alpha, beta)
+        {
             return entry.eval;
         }
-    }
+        let tt_move = tt_entry.map(|entry| entry.best_move);
 
-    let mut alpha = alpha.unwrap_or(EVAL_WORST);
-    let beta = beta.unwrap_or(EVAL_BEST);
+        let mut abs_best = EVAL_WORST;
+        let mut best_mv: Option<Move> = None;
 
-    let mut move_list = Vec::new();
-    board.generate_moves(|moves| {
-        move_list.extend(moves);
-        false
-    });
+        if board.status() == GameStatus::Won {
+            return -(MATE - ply as EvalInt);
+        } else if board.status() == GameStatus::Drawn {
+            return self.draw_score(ply);
+        }
 
-    let mut abs_best = EVAL_WORST;
-    let mut best_mv: Option<Move> = move_list.first().copied();
+        let in_check = !board.checkers().is_empty();
+        let pv_node = beta - alpha > 1;
+        // The eval is meaningless in check, where every move must answer it.
+        let static_eval = if in_check { None } else { Some(self.static_eval(board, ply)) };
+        if let Some(eval) = self.null_move_prune(board, depth, ply, alpha, beta, static_eval) {
+            return eval;
+        }
+        let single_reply = in_check && legal_move_count(board) == 1;
+        let futile = !pv_node
+            && depth <= FUTILITY_DEPTH
+            && static_eval.is_some_and(|eval| eval + FUTILITY_MARGIN * (depth as EvalInt) <= alpha);
+
+        let mut picker = MovePicker::new(tt_move, self.killers[ply]);
+        let mut quiets_tried = Vec::new();
+        let mut moves_searched = 0;
+        while let Some(mv) = picker.next(board, &self.history) {
+            if Some(mv) == excluded {
+                continue;
+            }
+            let quiet = is_quiet(board, mv);
+            // Pruning only starts once a move has been searched, so that a
+            // node never returns without a real score.
+            let can_prune = !in_check && abs_best > -MATE_BOUND;
+            if can_prune
+                && depth <= SEE_PRUNE_DEPTH
+                && !quiet
+                && !see_ge(board, mv, -SEE_PRUNE_MARGIN * depth as EvalInt)
+            {
+                continue;
+            }
+            if can_prune && !pv_node && quiet && depth <= LMP_DEPTH && quiets_tried.len() >= LMP_BASE + depth * depth {
+                continue;
+            }
+
+            let mut new_board = board.clone();
+            new_board.play(mv);
+            let gives_check = !new_board.checkers().is_empty();
+            if can_prune && futile && quiet && !gives_check {
+                continue;
+            }
+            let extension = self.extension(board, mv, depth, ply, tt_entry, gives_check, single_reply);
+            let child_depth = depth - 1 + extension;
+            self.stack[ply + 1] = StackEntry {
+                extensions: self.stack[ply].extensions + extension,
+                eval_state: self.child_eval_state(board, ply, mv),
+                ..StackEntry::default()
+            };
+
+            // Late move reductions: quiet moves late in the ordering rarely
+            // turn out best, so they are first searched shallower with a null
+            // window, and only searched properly if they beat alpha anyway.
+            let mut reduction = 0;
+            if depth >= LMR_DEPTH && moves_searched >= LMR_MIN_MOVES && quiet && !in_check && !gives_check {
+                let mut r = self.reductions[depth.min(63)][moves_searched.min(63)] as i32;
+                r -= pv_node as i32;
+                r -= self.history.get(board.side_to_move(), mv) / LMR_HISTORY_DIVISOR;
+                reduction = r.clamp(0, child_depth as i32 - 1) as usize;
+            }
+            moves_searched += 1;
+
+            let abs_score = if reduction > 0 {
+                let score = -self.minmax(&mut new_board, child_depth - reduction, ply + 1, Some(-alpha - 1), Some(-alpha));
+                if score > alpha && !self.stopped {
+                    self.pvs(&mut new_board, child_depth, ply + 1, alpha, beta, false)
+                } else {
+                    score
+                }
+            } else {
+                self.pvs(&mut new_board, child_depth, ply + 1, alpha, beta, best_mv.is_none())
+            };
+            if self.stopped {
+                return 0;
+            }
+            if abs_score > abs_best || best_mv.is_none() {
+                abs_best = abs_score;
+                best_mv = Some(mv);
+            }
+            if abs_score > alpha {
+                self.update_pv(ply, mv);
+            }
+            alpha = max(alpha, abs_best);
+            if alpha >= beta {
+                if quiet {
+                    self.update_quiet_stats(board, ply, depth, mv, &quiets_tried);
+                }
+                break;
+            }
+            if quiet {
+                quiets_tried.push(mv);
+            }
+        }
 
-    if board.status() == GameStatus::Won {
-        return EVAL_WORST;
-    } else if board.status() == GameStatus::Drawn {
-        return 0;
+        if let Some(best_mv) = best_mv && excluded.is_none() {
+            self.transposition_table.set(
+                board,
+                ply,
+                TranspositionEntry {
+                    best_move: best_mv,
+                    eval: abs_best,
+                    depth: depth as u8,
+                    bound: Bound::from_window(abs_best, alpha_orig, beta),
+                },
+            );
+        }
+
+        abs_best
     }
-    
-
-    for mv in move_list {
-        let mut new_board = board.clone();
-        new_board.play(mv);
-        let mut abs_score = 0;
-        if new_board.checkers() == BitBoard::EMPTY { // is someone in check
-            abs_score = -minmax(&mut new_board, depth-1, Some(-beta),Some(-alpha), transpositionTable);
-        } else {
-            abs_score = -minmax(&mut new_board, depth, Some(-beta),Some(-alpha), transpositionTable);
+
+    // Decides how much further than usual to search mv: one ply for a move
+    // that gives check, for the only legal reply to a check, or for a
+    // singular move, one that is much better than every alternative. A line
+    // may only be extended once for every two plies it has gone, so that
+    // lines of endless checks still come to an end.
+    // Returns: The extension in plies, 0 or 1.
+    #[allow(clippy::too_many_arguments)]
+    fn extension(
+        &mut self,
+        board: &Board,
+        mv: Move,
+        depth: usize,
+        ply: usize,
+        tt_entry: Option<TranspositionEntry>,
+        gives_check: bool,
+        single_reply: bool,
+    ) -> usize {
+        if 2 * self.stack[ply].extensions > ply {
+            return 0;
         }
-        if abs_score > abs_best {
-            abs_best = abs_score;
-            best_mv = Some(mv);
+        if gives_check || single_reply {
+            return 1;
         }
-        alpha = max(alpha,abs_best);
-        if alpha >= beta {
-            break;
+        self.is_singular(board, mv, depth, ply, tt_entry) as usize
+    }
+
+    // Checks whether the transposition table move is singular by searching
+    // every other move to half depth against a window just below the table's
+    // eval. If none of them comes close, the move is forced and worth a
+    // closer look.
+    fn is_singular(&mut self, board: &Board, mv: Move, depth: usize, ply: usize, tt_entry: Option<TranspositionEntry>) -> bool {
+        let Some(entry) = tt_entry else {
+            return false;
+        };
+        if depth < SINGULAR_DEPTH
+            || entry.best_move != mv
+            || (entry.depth as usize) + SINGULAR_TT_DEPTH < depth
+            || entry.bound == Bound::Upper
+            || entry.eval.abs() >= MATE_BOUND
+            || self.stack[ply].excluded.is_some()
+        {
+            return false;
         }
+        let singular_beta = entry.eval - SINGULAR_MARGIN * depth as EvalInt;
+        self.stack[ply].excluded = Some(mv);
+        let score = self.minmax(&mut board.clone(), (depth - 1) / 2, ply, Some(singular_beta - 1), Some(singular_beta));
+        self.stack[ply].excluded = None;
+        score < singular_beta && !self.stopped
     }
 
-    if !best_mv.is_none() {
-        transpositionTable.set(
-            &board,
-            TranspositionEntry {
-                best_move: best_mv.unwrap(),
-                eval: abs_best,
-                depth: depth as u8,
-            },
-        );
+    // Null-move pruning: lets the opponent move twice in a row. If a reduced
+    // search still fails high, a real move would almost surely do better
+    // still, so the node is cut off without searching any moves. Passing is
+    // never tried in check, at PV nodes, twice in a row, or when the side to
+    // move has only pawns left, where zugzwang makes passing too optimistic.
+    // Returns: The eval to cut off with, or None if the node must be searched.
+    fn null_move_prune(
+        &mut self,
+        board: &Board,
+        depth: usize,
+        ply: usize,
+        alpha: EvalInt,
+        beta: EvalInt,
+        static_eval: Option<EvalInt>,
+    ) -> Option<EvalInt> {
+        let us = board.colors(board.side_to_move());
+        let pawns_and_king = board.pieces(Piece::Pawn) | board.pieces(Piece::King);
+        if depth < NULL_MOVE_DEPTH
+            || static_eval.is_none_or(|eval| eval < beta)
+            || beta - alpha > 1
+            || beta.abs() >= MATE_BOUND
+            || ply < self.null_min_ply
+            || self.stack[ply].excluded.is_some()
+            || self.stack[ply].null_move
+            || (us & !pawns_and_king).is_empty()
+        {
+            return None;
+        }
+        let mut child = board.null_move()?;
+
+        let reduction = NULL_MOVE_REDUCTION + depth / NULL_MOVE_DIVISOR;
+        let child_depth = depth.saturating_sub(reduction + 1);
+        self.stack[ply + 1] = StackEntry {
+            null_move: true,
+            extensions: self.stack[ply].extensions,
+            eval_state: self.stack[ply].eval_state,
+            ..StackEntry::default()
+        };
+        let score = -self.minmax(&mut child, child_depth, ply + 1, Some(-beta), Some(-beta + 1));
+        self.stack[ply + 1].null_move = false;
+        if self.stopped || score < beta {
+            return None;
+        }
+        // A mate found after passing is not a real mate.
+        let score = if score >= MATE_BOUND { beta } else { score };
+        if depth < NULL_VERIFY_DEPTH {
+            return Some(score);
+        }
+
+        // This may itself be inside another verification, whose guard has to
+        // hold again once this one is done.
+        let outer_min_ply = self.null_min_ply;
+        self.null_min_ply = ply + 3 * (depth - reduction) / 4;
+        let verified = self.minmax(&mut board.clone(), depth - reduction, ply, Some(beta - 1), Some(beta));
+        self.null_min_ply = outer_min_ply;
+        (verified >= beta && !self.stopped).then_some(score)
+    }
+
+    // Principal variation search of a child position, from the parent's point
+    // of view. Only the first move is searched with the full window; the rest
+    // are expected to be worse, which a null window around alpha proves
+    // cheaply. A move that beats alpha anyway is searched again properly.
+    fn pvs(&mut self, child: &mut Board, depth: usize, ply: usize, alpha: EvalInt, beta: EvalInt, first: bool) -> EvalInt {
+        if first || beta - alpha <= 1 {
+            return -self.minmax(child, depth, ply, Some(-beta), Some(-alpha));
+        }
+        let score = -self.minmax(child, depth, ply, Some(-alpha - 1), Some(-alpha));
+        if score > alpha && score < beta && !self.stopped {
+            return -self.minmax(child, depth, ply, Some(-beta), Some(-alpha));
+        }
+        score
     }
 
+    // Searches every root move not in excluded to the given depth, within the
+    // window alpha..beta. MultiPV excludes the moves already reported this
+    // iteration to find the next best line.
+    // Returns: The best move and its eval, or None if the search was stopped
+    // before the iteration completed.
+    fn search_root(
+        &mut self,
+        board: &mut Board,
+        move_list: &[Move],
+        excluded: &[Move],
+        depth: usize,
+        alpha: EvalInt,
+        beta: EvalInt,
+    ) -> Option<(Move, EvalInt)> {
+        let alpha_orig = alpha;
+        let mut alpha = alpha;
+        let mut best_eval = EVAL_WORST;
+        let mut best_mv: Option<Move> = None;
+        let mut best_line = Vec::new();
+        self.stack[0] = StackEntry {
+            hash: board.hash(),
+            eval_state: EvalState::new(board),
+            ..StackEntry::default()
+        };
 
+        // The previous iteration's best move goes first, so that the other
+        // moves only have to be checked against its score.
+        let hash_move = self.transposition_table.probe(board, 0).map(|entry| entry.best_move);
+        let first = hash_move.filter(|mv| move_list.contains(mv) && !excluded.contains(mv));
+        let rest = move_list.iter().copied().filter(|&mv| Some(mv) != first && !excluded.contains(&mv));
+        for mv in first.into_iter().chain(rest) {
+            let mut new_board = board.clone();
+            new_board.play(mv);
+            self.stack[1] = StackEntry {
+                eval_state: self.child_eval_state(board, 0, mv),
+                ..StackEntry::default()
+            };
 
-    return abs_best;
-}
+            let abs_eval = self.pvs(&mut new_board, depth - 1, 1, alpha, beta, best_mv.is_none());
+            if self.stopped {
+                // A partial first iteration is still better than no move at all.
+                return if depth == 1 { best_mv.map(|mv| (mv, best_eval)) } else { None };
+            }
+
+            if abs_eval > best_eval || best_mv.is_none() {
+                best_eval = abs_eval;
+                best_mv = Some(mv);
+                self.update_pv(0, mv);
+                best_line.clone_from(&self.pv[0]);
+            }
+            alpha = max(alpha, best_eval);
+            if alpha >= beta {
+                break;
+            }
+        }
 
+        let best_mv = best_mv?;
+        self.pv[0] = best_line;
+        if excluded.is_empty() {
+            self.transposition_table.set(
+                board,
+                0,
+                TranspositionEntry {
+                    best_move: best_mv,
+                    eval: best_eval,
+                    depth: depth as u8,
+                    bound: Bound::from_window(best_eval, alpha_orig, beta),
+                },
+            );
+        }
+        Some((best_mv, best_eval))
+    }
 
+    // Searches the root within a narrow window around the eval the previous
+    // iteration found for this line, widening it each time the search falls
+    // outside. Narrow windows prune more, and the eval rarely moves far
+    // between iterations.
+    // Returns: As search_root, but the eval is always exact.
+    fn search_root_aspiration(
+        &mut self,
+        board: &mut Board,
+        move_list: &[Move],
+        excluded: &[Move],
+        depth: usize,
+        previous: Option<EvalInt>,
+    ) -> Option<(Move, EvalInt)> {
+        let mut delta = ASPIRATION_DELTA;
+        let (mut alpha, mut beta) = match previous {
+            Some(eval) if depth >= ASPIRATION_DEPTH && eval.abs() < MATE_BOUND => {
+                ((eval - delta).max(EVAL_WORST), (eval + delta).min(EVAL_BEST))
+            }
+            _ => (EVAL_WORST, EVAL_BEST),
+        };
+        loop {
+            let (mv, eval) = self.search_root(board, move_list, excluded, depth, alpha, beta)?;
+            if self.stopped {
+                return Some((mv, eval));
+            }
+            if eval <= alpha && alpha > EVAL_WORST {
+                alpha = (eval - delta).max(EVAL_WORST);
+            } else if eval >= beta && beta < EVAL_BEST {
+                beta = (eval + delta).min(EVAL_BEST);
+            } else {
+                return Some((mv, eval));
+            }
+            delta *= 2;
+        }
+    }
+
+    // Prints the UCI info line for one line of a completed iteration.
+    fn print_info(&self, board: &Board, depth: usize, multipv: usize, line: &RootLine) {
+        let elapsed = self.start.elapsed();
+        let millis = elapsed.as_millis();
+        let nodes = self.total_nodes();
+        let nps = (nodes as f64 / elapsed.as_secs_f64().max(0.001)) as u64;
+        let mut pv_board = board.clone();
+        let mut pv = Vec::new();
+        for &mv in &line.pv {
+            pv.push(display_uci_move(&pv_board, mv).to_string());
+            pv_board.play_unchecked(mv);
+        }
+        println!(
+            "info depth {} seldepth {} multipv {} score {} nodes {} nps {} time {} hashfull {} pv {}",
+            depth,
+            self.seldepth,
+            multipv,
+            uci_score(line.eval),
+            nodes,
+            nps,
+            millis,
+            self.transposition_table.hashfull(),
+            pv.join(" "),
+        );
+    }
+
+    // Helper threads of Lazy SMP search the same root as the main thread,
+    // silently, so that the main thread finds their results in the shared
+    // table. Odd helpers start a ply deeper and each visits the root moves
+    // in a different order, so the threads do not search in lockstep.
+    fn help(&mut self, board: &mut Board, move_list: &mut [Move], id: usize, max_depth: usize) {
+        if !move_list.is_empty() {
+            let len = move_list.len();
+            move_list.rotate_left(id % len);
+        }
+        for depth in (1 + id % 2)..=max_depth {
+            self.search_root(board, move_list, &[], depth, EVAL_WORST, EVAL_BEST);
+            if self.stopped {
+                break;
+            }
+        }
+    }
+}
+
+// Finds the best move for a position by iterative deepening, stopping once
+// the depth, node or time limit is hit or the stop signal is raised.
+// Returns: The best move and the reply we expect, to ponder on.
+fn search(
+    board: &mut Board,
+    game_history: &[u64],
+    transposition_table: &HashTable,
+    limits: &SearchLimits,
+    options: &EngineOptions,
+    signals: &SearchSignals,
+) -> Option<(Move, Option<Move>)> {
+    let start = Instant::now();
+    let (soft_limit, hard_limit) = limits.time_budget(board.side_to_move(), options.move_overhead);
+    let max_depth = limits.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH);
 
-// Finds the best move for a position
-fn search(board: &mut Board, transpositionTable: &mut HashTable) -> Option<Move> {
-    const DEPTH: usize = 2;
     let mut move_list = Vec::new();
     board.generate_moves(|moves| {
         move_list.extend(moves);
         false
     });
-
-    let mut best_eval = EVAL_WORST;
     let mut best_mv: Option<Move> = move_list.first().copied();
 
-    if let Some(entry) = transpositionTable.probe(&board) {
-        if entry.depth as usize >= DEPTH {
-            return Some(entry.best_move);
+    transposition_table.new_search();
+    let shared = SharedSearch {
+        game_history: game_history.to_vec(),
+        contempt: options.contempt,
+        ..SharedSearch::default()
+    };
+    let mut searcher = Searcher::new(transposition_table, signals, &shared, limits, start, hard_limit);
+
+    let mut best_line = Vec::new();
+    thread::scope(|scope| {
+        for id in 1..options.threads {
+            let mut board = board.clone();
+            let mut move_list = move_list.clone();
+            let shared = &shared;
+            scope.spawn(move || {
+                let mut helper = Searcher::new(transposition_table, signals, shared, limits, start, hard_limit);
+                helper.help(&mut board, &mut move_list, id, max_depth);
+            });
+        }
+
+        let multipv = options.multipv.clamp(1, move_list.len().max(1));
+        let mut previous_evals: Vec<EvalInt> = Vec::new();
+        for depth in 1..=max_depth {
+            let mut lines: Vec<RootLine> = Vec::with_capacity(multipv);
+            let mut excluded = Vec::with_capacity(multipv);
+            while lines.len() < multipv {
+                let previous = previous_evals.get(lines.len()).copied();
+                let Some((mv, eval)) =
+                    searcher.search_root_aspiration(board, &move_list, &excluded, depth, previous)
+                else {
+                    break;
+                };
+                excluded.push(mv);
+                lines.push(RootLine {
+                    eval,
+                    pv: searcher.pv[0].clone(),
+                });
+                if searcher.stopped {
+                    break;
+                }
+            }
+            // Later lines can outscore earlier ones when the earlier search was
+            // cut short by the table, so keep them in order.
+            lines.sort_by_key(|line| Reverse(line.eval));
+            // Only a completed iteration is trusted, apart from the first, which
+            // has nothing better to fall back on.
+            if let Some(line) = lines.first()
+                && (!searcher.stopped || depth == 1)
+            {
+                best_mv = line.pv.first().copied();
+                best_line.clone_from(&line.pv);
+                previous_evals = lines.iter().map(|line| line.eval).collect();
+                // Search this iteration's best moves first next time, as
+                // they are the likeliest to stay best.
+                for mv in lines.iter().rev().filter_map(|line| line.pv.first()) {
+                    if let Some(i) = move_list.iter().position(|m| m == mv) {
+                        move_list[..=i].rotate_right(1);
+                    }
+                }
+                if !searcher.stopped {
+                    for (i, line) in lines.iter().enumerate() {
+                        searcher.print_info(board, depth, i + 1, line);
+                    }
+                }
+            }
+            if searcher.stopped || searcher.out_of_time(soft_limit) {
+                break;
+            }
         }
+
+        // UCI forbids answering an infinite or ponder search before the GUI says
+        // stop or ponderhit.
+        while (limits.infinite || signals.ponder.load(Ordering::Relaxed)) && !signals.stop.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        shared.done.store(true, Ordering::Relaxed);
+    });
+
+    let best_mv = best_mv?;
+    let ponder_mv = if best_line.first() == Some(&best_mv) && best_line.len() > 1 {
+        Some(best_line[1])
+    } else {
+        // The line was cut short, most likely by a transposition table hit,
+        // so fall back to the table's move for the position after ours.
+        let mut next_board = board.clone();
+        next_board.play(best_mv);
+        searcher
+            .transposition_table
+            .probe(&next_board, 1)
+            .map(|entry| entry.best_move)
+            .filter(|&mv| next_board.is_legal(mv))
+    };
+
+    Some((best_mv, ponder_mv))
+}
+
+/// Find the best move and a move to ponder on, or None if the side to move
+/// has no legal moves.
+pub fn best_move(
+    board: &mut Board,
+    game_history: &[u64],
+    transposition_table: &HashTable,
+    limits: &SearchLimits,
+    options: &EngineOptions,
+    signals: &SearchSignals,
+) -> Option<(Move, Option<Move>)> {
+    search(board, game_history, transposition_table, limits, options, signals)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::search::*;
+
+    fn budget(limits: SearchLimits, side: Color, move_overhead: u64) -> (u64, u64) {
+        let (soft, hard) = limits.time_budget(side, move_overhead);
+        (soft.unwrap().as_millis() as u64, hard.unwrap().as_millis() as u64)
+    }
+
+    // The positions along a line from the start position, where 0000 is a
+    // null move.
+    fn line(moves: &str) -> Vec<Board> {
+        let mut boards = vec![Board::default()];
+        for mv in moves.split_whitespace() {
+            let mut board = boards.last().unwrap().clone();
+            if mv == "0000" {
+                board = board.null_move().unwrap();
+            } else {
+                board.play(mv.parse().unwrap());
+            }
+            boards.push(board);
+        }
+        boards
     }
 
-    for mv in move_list {
-        let mut new_board = board.clone();
-        new_board.play(mv);
+    #[test]
+    fn test_time_budget() {
+        // A fixed move time, less the overhead but never down to nothing.
+        let movetime = SearchLimits { movetime: Some(1000), ..SearchLimits::default() };
+        assert_eq!(budget(movetime, Color::White, 30), (970, 970));
+        let movetime = SearchLimits { movetime: Some(10), ..SearchLimits::default() };
+        assert_eq!(budget(movetime, Color::White, 30), (1, 1));
 
-        
+        // Thirty moves to go by default, plus most of the increment. Each side
+        // uses its own clock.
+        let clock = SearchLimits { wtime: Some(10000), winc: Some(1000), btime: Some(100), ..SearchLimits::default() };
+        assert_eq!(budget(clock.clone(), Color::White, 0), (1083, 3249));
+        assert_eq!(budget(clock, Color::Black, 0), (3, 9));
 
-        let abs_eval = -minmax(&mut new_board, DEPTH, None, None, transpositionTable);
+        // movestogo is clamped to 1..=50, and the soft limit never passes the
+        // hard one, which never passes half the clock.
+        let last_move = SearchLimits { wtime: Some(60030), movestogo: Some(0), ..SearchLimits::default() };
+        assert_eq!(budget(last_move, Color::White, 30), (30000, 30000));
+        let far_off = SearchLimits { wtime: Some(60030), movestogo: Some(1000), ..SearchLimits::default() };
+        assert_eq!(budget(far_off, Color::White, 30), (1200, 3600));
+
+        // An empty clock still leaves a limit rather than none.
+        let flagged = SearchLimits { wtime: Some(0), ..SearchLimits::default() };
+        let (soft, hard) = budget(flagged, Color::White, 30);
+        assert!(soft <= hard && hard <= 1);
+
+        let infinite = SearchLimits { wtime: Some(1000), infinite: true, ..SearchLimits::default() };
+        assert_eq!(infinite.time_budget(Color::White, 30), (None, None));
+        assert_eq!(SearchLimits::default().time_budget(Color::White, 30), (None, None));
+    }
 
-        
-        if abs_eval > best_eval {
-            best_eval = abs_eval;
-            best_mv = Some(mv);
+    #[test]
+    fn test_uci_score() {
+        assert_eq!(uci_score(35), "cp 35");
+        // Mating in 1 ply is mate in 1 move, in 3 plies mate in 2.
+        assert_eq!(uci_score(MATE - 1), "mate 1");
+        assert_eq!(uci_score(MATE - 3), "mate 2");
+        // Being mated in 2 plies is mate in -1.
+        assert_eq!(uci_score(-(MATE - 2)), "mate -1");
+        assert_eq!(uci_score(-MATE), "mate 0");
+    }
+
+    #[test]
+    fn test_fifty_move_rule_beats_table() {
+        let table = HashTable::new(1);
+        let signals = SearchSignals::default();
+        let shared = SharedSearch::default();
+        let mut searcher = Searcher::new(&table, &signals, &shared, &SearchLimits::default(), Instant::now(), None);
+        // The hash does not include the halfmove clock, so this entry could
+        // have been stored for the same position earlier in the game.
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 100 120", false).unwrap();
+        let mv: Move = "d1d7".parse().unwrap();
+        table.set(&board, 1, TranspositionEntry { best_move: mv, eval: 2500, depth: 20, bound: Bound::Exact });
+        let ply = 1;
+        searcher.stack[ply].eval_state = EvalState::new(&board);
+        assert_eq!(searcher.minmax(&mut board.clone(), 4, ply, None, None), searcher.draw_score(ply));
+    }
+
+    #[test]
+    fn test_nested_null_verification_keeps_outer_guard() {
+        let table = HashTable::new(1);
+        let signals = SearchSignals::default();
+        let shared = SharedSearch::default();
+        let mut searcher = Searcher::new(&table, &signals, &shared, &SearchLimits::default(), Instant::now(), None);
+        // A queen up, so passing still fails high and is verified.
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1", false).unwrap();
+        let ply = 2;
+        searcher.stack[ply].eval_state = EvalState::new(&board);
+        // As if an outer verification had turned null moves off before this
+        // ply. The inner one runs at that ply and must hand the guard back.
+        searcher.null_min_ply = ply;
+        let eval = searcher.static_eval(&board, ply);
+        let cutoff = searcher.null_move_prune(&board, NULL_VERIFY_DEPTH, ply, -1, 0, Some(eval));
+        assert!(cutoff.is_some());
+        assert_eq!(searcher.null_min_ply, ply);
+    }
+
+    #[test]
+    fn test_is_repetition() {
+        let table = HashTable::new(1);
+        let signals = SearchSignals::default();
+        let moves = "g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1 f6g8";
+
+        // Once is enough inside the search.
+        let shared = SharedSearch::default();
+        let mut searcher = Searcher::new(&table, &signals, &shared, &SearchLimits::default(), Instant::now(), None);
+        let boards = line(moves);
+        for (ply, board) in boards[..4].iter().enumerate() {
+            searcher.stack[ply].hash = board.hash();
         }
+        assert!(searcher.is_repetition(&boards[4], 4));
+        assert!(!searcher.is_repetition(&boards[3], 3));
+
+        // Before the root it has to be the third time the position is seen.
+        let history = boards.iter().map(|board| board.hash()).collect::<Vec<_>>();
+        let shared = SharedSearch { game_history: history[..4].to_vec(), ..SharedSearch::default() };
+        let searcher = Searcher::new(&table, &signals, &shared, &SearchLimits::default(), Instant::now(), None);
+        assert!(!searcher.is_repetition(&boards[4], 0));
+        let shared = SharedSearch { game_history: history[..8].to_vec(), ..SharedSearch::default() };
+        let searcher = Searcher::new(&table, &signals, &shared, &SearchLimits::default(), Instant::now(), None);
+        assert!(searcher.is_repetition(&boards[8], 0));
+
+        // Passing twice brings back the start position, but not by a real
+        // line of play.
+        let shared = SharedSearch::default();
+        let mut searcher = Searcher::new(&table, &signals, &shared, &SearchLimits::default(), Instant::now(), None);
+        let boards = line("g1f3 0000 f3g1 0000");
+        assert_eq!(boards[4].hash(), boards[0].hash());
+        for (ply, board) in boards[..4].iter().enumerate() {
+            searcher.stack[ply].hash = board.hash();
+        }
+        searcher.stack[2].null_move = true;
+        searcher.stack[4].null_move = true;
+        assert!(!searcher.is_repetition(&boards[4], 4));
     }
-    transpositionTable.set(
-        &board,
-        TranspositionEntry {
-            best_move: best_mv.unwrap(),
-            eval: best_eval,
-            depth: DEPTH as u8,
-        },
-    );
-
-    return best_mv;
 }
-
-/// Find the best move.
-pub fn best_move(board: &mut Board, transpositionTable: &mut HashTable) -> Move {
-    return search(board, transpositionTable).unwrap();
-}
\ No newline at end of file
@@ -0,0 +1,145 @@
+// This file works out static exchange evaluation (SEE): what a capture wins
+// or loses in material once both sides have made every recapture on the
+// square that is worth making. No moves are searched; it only looks at which
+// pieces attack the square, so pins and checks are ignored.
+
+use crate::eval::*;
+use crate::movepick::*;
+use cozy_chess::*;
+
+// Piece values for exchanges. The king's value is large so that it is never
+// worth putting it where it could be taken.
+const SEE_VALUES: [EvalInt; Piece::NUM] = [100, 300, 300, 500, 900, 20000];
+
+pub fn piece_value(piece: Piece) -> EvalInt {
+    SEE_VALUES[piece as usize]
+}
+
+// Every piece of either colour in occupied that attacks sq. Sliders are
+// looked up through occupied, so removing a piece that has taken part in the
+// exchange reveals any x-ray attacker behind it.
+fn attackers(board: &Board, sq: Square, occupied: BitBoard) -> BitBoard {
+    let queens = board.pieces(Piece::Queen);
+    let diagonal = board.pieces(Piece::Bishop) | queens;
+    let orthogonal = board.pieces(Piece::Rook) | queens;
+    let pawns = board.pieces(Piece::Pawn);
+
+    let attackers = (get_bishop_moves(sq, occupied) & diagonal)
+        | (get_rook_moves(sq, occupied) & orthogonal)
+        | (get_knight_moves(sq) & board.pieces(Piece::Knight))
+        | (get_king_moves(sq) & board.pieces(Piece::King))
+        | (get_pawn_attacks(sq, Color::White) & pawns & board.colors(Color::Black))
+        | (get_pawn_attacks(sq, Color::Black) & pawns & board.colors(Color::White));
+    attackers & occupied
+}
+
+// Finds the least valuable piece of a set, which is the one that should
+// make the next capture.
+fn least_valuable(board: &Board, set: BitBoard) -> Option<(Square, Piece)> {
+    Piece::ALL
+        .iter()
+        .find_map(|&piece| (set & board.pieces(piece)).next_square().map(|sq| (sq, piece)))
+}
+
+/// The material the side to move gains by playing mv and then trading off
+/// on its target square for as long as that is profitable for either side.
+/// Negative when the move loses material.
+pub fn see(board: &Board, mv: Move) -> EvalInt {
+    let us = board.side_to_move();
+    let Some(mover) = board.piece_on(mv.from) else {
+        return 0;
+    };
+    // Castling is encoded as the king taking its own rook.
+    if board.colors(us).has(mv.to) {
+        return 0;
+    }
+
+    let mut occupied = board.occupied() ^ mv.from.bitboard();
+    let mut gain = [0 as EvalInt; 32];
+    gain[0] = match board.piece_on(mv.to) {
+        Some(victim) => piece_value(victim),
+        None if is_capture(board, mv) => {
+            // En passant: the captured pawn is beside the target square.
+            occupied ^= Square::new(mv.to.file(), mv.from.rank()).bitboard();
+            piece_value(Piece::Pawn)
+        }
+        None => 0,
+    };
+    let mut on_square = match mv.promotion {
+        Some(promotion) => {
+            gain[0] += piece_value(promotion) - piece_value(Piece::Pawn);
+            piece_value(promotion)
+        }
+        None => piece_value(mover),
+    };
+
+    // gain[d] is what the side making the d-th capture has won so far,
+    // assuming the exchange stops right after it.
+    let mut side = !us;
+    let mut d = 0;
+    loop {
+        let ours = attackers(board, mv.to, occupied) & board.colors(side);
+        let Some((sq, piece)) = least_valuable(board, ours) else {
+            break;
+        };
+        d += 1;
+        gain[d] = on_square - gain[d - 1];
+        if d == gain.len() - 1 {
+            break;
+        }
+        occupied ^= sq.bitboard();
+        on_square = piece_value(piece);
+        side = !side;
+    }
+
+    // Each side stops the exchange once carrying on would lose.
+    while d > 0 {
+        gain[d - 1] = -(-gain[d - 1]).max(gain[d]);
+        d -= 1;
+    }
+    gain[0]
+}
+
+/// The value of the piece mv captures, or 0 if it captures nothing.
+pub fn captured_value(board: &Board, mv: Move) -> EvalInt {
+    if !is_capture(board, mv) {
+        return 0;
+    }
+    // Only en passant captures onto an empty square.
+    board.piece_on(mv.to).map_or(piece_value(Piece::Pawn), piece_value)
+}
+
+/// Whether mv wins at least threshold material by static exchange.
+pub fn see_ge(board: &Board, mv: Move, threshold: EvalInt) -> bool {
+    see(board, mv) >= threshold
+}
+
+#[cfg(test)]
+mod tests {
+    use cozy_chess::*;
+    use crate::see::*;
+
+    fn see_of(fen: &str, mv: &str) -> EvalInt {
+        let board = Board::from_fen(fen, false).unwrap();
+        see(&board, mv.parse().unwrap())
+    }
+
+    #[test]
+    fn test_see() {
+        // Undefended pawn.
+        assert_eq!(see_of("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"), 100);
+        // Queen takes a pawn defended by a pawn.
+        assert_eq!(see_of("4k3/8/3p4/4p3/8/8/8/4QK2 w - - 0 1", "e1e5"), -800);
+        // Pawn takes a defended queen.
+        assert_eq!(see_of("4k3/8/3p4/4q3/3P4/8/8/5K2 w - - 0 1", "d4e5"), 800);
+        // Knight takes a pawn defended once, but backed up by a rook x-raying
+        // through the queen in front of it.
+        assert_eq!(see_of("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1", "d3e5"), -200);
+        // Doubled rooks take a pawn defended by doubled rooks.
+        assert_eq!(see_of("3r2k1/3r4/8/3p4/8/8/3R4/3R2K1 w - - 0 1", "d2d5"), -400);
+        // The same pawn with only one rook defending it.
+        assert_eq!(see_of("6k1/3r4/8/3p4/8/8/3R4/3R2K1 w - - 0 1", "d2d5"), 100);
+        // En passant.
+        assert_eq!(see_of("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), 100);
+    }
+}
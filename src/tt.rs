@@ -0,0 +1,381 @@
+// This file holds the transposition table, which remembers what the search
+// learned about positions it has already visited. Every search thread shares
+// one table, so entries are read and written with atomics instead of a lock.
+
+use crate::eval::*;
+use crate::search::MATE_BOUND;
+use cozy_chess::*;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+
+// What an entry's eval says about the true value of the position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    // The search saw the whole window, so the eval is exact.
+    Exact,
+    // The search failed high: the true value is at least the eval.
+    Lower,
+    // The search failed low: the true value is at most the eval.
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TranspositionEntry {
+    pub best_move: Move,
+    pub eval: EvalInt,
+    pub depth: u8,
+    pub bound: Bound,
+}
+
+// Generations are stored in 6 bits and wrap around.
+const AGE_MASK: u8 = 0b111111;
+
+// A new result for a position replaces the one already stored for it only if
+// it is at most this much shallower, unless it is exact or the old one is
+// from an earlier search.
+const REPLACE_DEPTH_MARGIN: i32 = 2;
+
+// Entries per cluster. Eight 8-byte entries fill one 64-byte cache line, so a
+// probe touches a single line of memory.
+const CLUSTER_SIZE: usize = 8;
+
+// Each entry is one 64-bit word, so it is always read and written whole and
+// threads can never see half of someone else's write:
+//   bits  0-15  best move
+//   bits 16-31  eval
+//   bits 32-39  depth
+//   bits 40-41  bound
+//   bits 42-47  age
+//   bits 48-63  the low 16 bits of the position's hash
+// The cluster is picked with the hash's high bits, so the stored key bits
+// are independent of the ones already implied by the entry's location.
+#[repr(align(64))]
+#[derive(Default)]
+struct Cluster {
+    entries: [AtomicU64; CLUSTER_SIZE],
+}
+
+pub struct HashTable {
+    data: Vec<Cluster>,
+    size: usize,
+    // Generation of the current search, stored with each entry so that
+    // entries left over from earlier searches are replaced first.
+    age: AtomicU8,
+}
+
+pub fn mib_to_n<T: Sized>(mib: usize) -> usize {
+    let bytes = mib * (1 << 20);
+    let entry_size = std::mem::size_of::<T>();
+
+    bytes / entry_size
+}
+
+// Packs a move into 16 bits: 6 for each square and 3 for the promotion.
+fn pack_move(mv: Move) -> u64 {
+    let promotion = mv.promotion.map_or(0, |piece| piece as u64 + 1);
+    mv.from as u64 | (mv.to as u64) << 6 | promotion << 12
+}
+
+fn unpack_move(bits: u64) -> Move {
+    let promotion = (bits >> 12) & 0b111;
+    Move {
+        from: Square::index((bits & 0b111111) as usize),
+        to: Square::index(((bits >> 6) & 0b111111) as usize),
+        promotion: (promotion != 0).then(|| Piece::index(promotion as usize - 1)),
+    }
+}
+
+impl TranspositionEntry {
+    fn pack(self, key: u16, age: u8) -> u64 {
+        let eval = self.eval.clamp(i16::MIN as EvalInt, i16::MAX as EvalInt) as i16;
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        pack_move(self.best_move)
+            | (eval as u16 as u64) << 16
+            | (self.depth as u64) << 32
+            | bound << 40
+            | ((age & AGE_MASK) as u64) << 42
+            | (key as u64) << 48
+    }
+
+    fn unpack(data: u64) -> Self {
+        TranspositionEntry {
+            best_move: unpack_move(data & 0xFFFF),
+            eval: ((data >> 16) & 0xFFFF) as u16 as i16 as EvalInt,
+            depth: ((data >> 32) & 0xFF) as u8,
+            bound: match (data >> 40) & 0b11 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+        }
+    }
+}
+
+fn unpack_age(data: u64) -> u8 {
+    ((data >> 42) as u8) & AGE_MASK
+}
+
+fn unpack_depth(data: u64) -> u8 {
+    (data >> 32) as u8
+}
+
+fn unpack_key(data: u64) -> u16 {
+    (data >> 48) as u16
+}
+
+// Mate scores count plies from the root, but an entry may be found again at
+// a different ply. They are stored counting from the entry's own position
+// instead, and converted back when probed.
+fn eval_to_tt(eval: EvalInt, ply: usize) -> EvalInt {
+    if eval >= MATE_BOUND {
+        eval + ply as EvalInt
+    } else if eval <= -MATE_BOUND {
+        eval - ply as EvalInt
+    } else {
+        eval
+    }
+}
+
+fn eval_from_tt(eval: EvalInt, ply: usize) -> EvalInt {
+    if eval >= MATE_BOUND {
+        eval - ply as EvalInt
+    } else if eval <= -MATE_BOUND {
+        eval + ply as EvalInt
+    } else {
+        eval
+    }
+}
+
+impl Bound {
+    // Whether an entry with this bound settles a search of the window
+    // alpha..beta without searching any further.
+    pub fn cuts_off(self, eval: EvalInt, alpha: EvalInt, beta: EvalInt) -> bool {
+        match self {
+            Bound::Exact => true,
+            Bound::Lower => eval >= beta,
+            Bound::Upper => eval <= alpha,
+        }
+    }
+
+    // The bound for a search of the window alpha..beta that returned eval.
+    pub fn from_window(eval: EvalInt, alpha: EvalInt, beta: EvalInt) -> Self {
+        if eval <= alpha {
+            Bound::Upper
+        } else if eval >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        }
+    }
+}
+
+impl Default for HashTable {
+    fn default() -> Self {
+        Self::new(8)
+    }
+}
+
+impl HashTable {
+    // Builds a table using exactly size_mib MiB for its entries.
+    pub fn new(size_mib: usize) -> Self {
+        let size = mib_to_n::<Cluster>(size_mib);
+        Self::new_n(size)
+    }
+
+    // Builds a table of size clusters.
+    pub fn new_n(size: usize) -> Self {
+        HashTable {
+            data: (0..size).map(|_| Cluster::default()).collect(),
+            size,
+            age: AtomicU8::new(0),
+        }
+    }
+
+    // Maps a hash onto a cluster by multiplying and keeping the high word,
+    // which is faster than a modulo and works for any table size.
+    pub fn trunc_hash(&self, hash: u64) -> usize {
+        ((hash as u128 * self.size as u128) >> 64) as usize
+    }
+
+    // Moves the table on to a new generation. Called at the start of every
+    // search.
+    pub fn new_search(&self) {
+        let age = self.age.load(Ordering::Relaxed);
+        self.age.store(age.wrapping_add(1) & AGE_MASK, Ordering::Relaxed);
+    }
+
+    // Looks up the entry for a position reached at the given ply.
+    pub fn probe(&self, board: &Board, ply: usize) -> Option<TranspositionEntry> {
+        let hash = board.hash();
+        let key = hash as u16;
+        let cluster = &self.data[self.trunc_hash(hash)];
+        for entry in &cluster.entries {
+            let data = entry.load(Ordering::Relaxed);
+            // An empty entry has no move, which nothing is stored without.
+            if data != 0 && unpack_key(data) == key {
+                let mut entry = TranspositionEntry::unpack(data);
+                entry.eval = eval_from_tt(entry.eval, ply);
+                return Some(entry);
+            }
+        }
+        None
+    }
+
+    // Stores the entry for a position reached at the given ply. If the
+    // position already has an entry, a much shallower inexact result from the
+    // same search is dropped rather than overwrite it. Otherwise it replaces
+    // whichever entry in the cluster is least valuable: the shallowest,
+    // counting entries from earlier searches as shallower the older they are.
+    pub fn set(&self, board: &Board, ply: usize, mut entry: TranspositionEntry) {
+        let hash = board.hash();
+        let key = hash as u16;
+        let age = self.age.load(Ordering::Relaxed);
+        let cluster = &self.data[self.trunc_hash(hash)];
+        entry.eval = eval_to_tt(entry.eval, ply);
+
+        let mut victim = &cluster.entries[0];
+        let mut victim_worth = i32::MAX;
+        for slot in &cluster.entries {
+            let data = slot.load(Ordering::Relaxed);
+            if data == 0 {
+                victim = slot;
+                break;
+            }
+            if unpack_key(data) == key {
+                let replace = entry.bound == Bound::Exact
+                    || unpack_age(data) != age
+                    || entry.depth as i32 + REPLACE_DEPTH_MARGIN >= unpack_depth(data) as i32;
+                if !replace {
+                    return;
+                }
+                victim = slot;
+                break;
+            }
+            let age_distance = age.wrapping_sub(unpack_age(data)) & AGE_MASK;
+            let worth = unpack_depth(data) as i32 - 8 * age_distance as i32;
+            if worth < victim_worth {
+                victim = slot;
+                victim_worth = worth;
+            }
+        }
+        victim.store(entry.pack(key, age), Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for cluster in &self.data {
+            for entry in &cluster.entries {
+                entry.store(0, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Estimates how full the table is by sampling its first thousand entries,
+    // counting only entries written by the current search.
+    // Returns: The occupancy in permille, as UCI's hashfull expects.
+    pub fn hashfull(&self) -> usize {
+        let clusters = self.size.min(1000 / CLUSTER_SIZE);
+        if clusters == 0 {
+            return 0;
+        }
+        let age = self.age.load(Ordering::Relaxed);
+        let used = self.data[..clusters]
+            .iter()
+            .flat_map(|cluster| &cluster.entries)
+            .filter(|entry| {
+                let data = entry.load(Ordering::Relaxed);
+                data != 0 && unpack_age(data) == age
+            })
+            .count();
+        used * 1000 / (clusters * CLUSTER_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cozy_chess::*;
+    use crate::search::MATE;
+    use crate::tt::*;
+
+    #[test]
+    fn test_probe_after_set() {
+        let table = HashTable::new(1);
+        let board = Board::default();
+        let mv: Move = "e7e8q".parse().unwrap();
+        table.set(&board, 0, TranspositionEntry { best_move: mv, eval: -1234, depth: 7, bound: Bound::Lower });
+
+        let entry = table.probe(&board, 0).unwrap();
+        assert_eq!(entry.best_move, mv);
+        assert_eq!(entry.eval, -1234);
+        assert_eq!(entry.depth, 7);
+        assert_eq!(entry.bound, Bound::Lower);
+
+        table.clear();
+        assert!(table.probe(&board, 0).is_none());
+    }
+
+    #[test]
+    fn test_mate_scores_are_ply_relative() {
+        let table = HashTable::new(1);
+        let board = Board::default();
+        let mv: Move = "e2e4".parse().unwrap();
+        // Mate in 3 plies from a position 5 plies from the root.
+        let eval = MATE - 8;
+        table.set(&board, 5, TranspositionEntry { best_move: mv, eval, depth: 3, bound: Bound::Exact });
+
+        assert_eq!(table.probe(&board, 5).unwrap().eval, eval);
+        assert_eq!(table.probe(&board, 1).unwrap().eval, MATE - 4);
+    }
+
+    #[test]
+    fn test_shallow_store_keeps_deeper_entry() {
+        let table = HashTable::new(1);
+        let board = Board::default();
+        let deep_move: Move = "e2e4".parse().unwrap();
+        let shallow_move: Move = "d2d4".parse().unwrap();
+        table.set(&board, 0, TranspositionEntry { best_move: deep_move, eval: 30, depth: 9, bound: Bound::Lower });
+
+        // A quiescence result for the same position does not replace it.
+        table.set(&board, 0, TranspositionEntry { best_move: shallow_move, eval: -5, depth: 0, bound: Bound::Upper });
+        let entry = table.probe(&board, 0).unwrap();
+        assert_eq!((entry.best_move, entry.depth, entry.bound), (deep_move, 9, Bound::Lower));
+
+        // An exact result does, and so does anything in a later search.
+        table.set(&board, 0, TranspositionEntry { best_move: shallow_move, eval: 5, depth: 1, bound: Bound::Exact });
+        assert_eq!(table.probe(&board, 0).unwrap().depth, 1);
+        table.set(&board, 0, TranspositionEntry { best_move: deep_move, eval: 30, depth: 9, bound: Bound::Lower });
+        table.new_search();
+        table.set(&board, 0, TranspositionEntry { best_move: shallow_move, eval: -5, depth: 0, bound: Bound::Upper });
+        assert_eq!(table.probe(&board, 0).unwrap().depth, 0);
+    }
+
+    #[test]
+    fn test_deep_entries_survive_shallow_ones() {
+        // A single cluster, so every position competes for the same entries.
+        let table = HashTable::new_n(1);
+        let mv: Move = "e2e4".parse().unwrap();
+        let deep = Board::default();
+        table.set(&deep, 0, TranspositionEntry { best_move: mv, eval: 10, depth: 9, bound: Bound::Exact });
+
+        let mut replies = Vec::new();
+        deep.generate_moves(|moves| {
+            replies.extend(moves);
+            false
+        });
+        for reply in replies {
+            let mut shallow = deep.clone();
+            shallow.play(reply);
+            table.set(&shallow, 1, TranspositionEntry { best_move: mv, eval: 20, depth: 1, bound: Bound::Exact });
+        }
+
+        assert_eq!(table.probe(&deep, 0).unwrap().depth, 9);
+    }
+
+    #[test]
+    fn test_size_matches_hash_option() {
+        assert_eq!(std::mem::size_of::<Cluster>(), 64);
+        assert_eq!(HashTable::new(16).size * 64, 16 << 20);
+    }
+}